@@ -0,0 +1,154 @@
+use crate::config::AccessConfig;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const NONCE_LEN: usize = 12;
+
+/// Who a stream token was issued to, checked by the HTTP server.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenClaims {
+    pub user_id: i64,
+    pub expires_at: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    Malformed,
+    BadSignature,
+    Expired,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Malformed => write!(f, "malformed stream token"),
+            TokenError::BadSignature => write!(f, "invalid stream token"),
+            TokenError::Expired => write!(f, "stream token has expired"),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+/// Issues and checks the expiring, HMAC signed tokens sent by `/getvideo`.
+///
+/// A token is `<expires>.<user id>.<nonce>.<signature>`, all parts are URL safe.
+pub struct StreamTokens {
+    key: hmac::Key,
+    ttl: Duration,
+    rng: SystemRandom,
+}
+
+impl fmt::Debug for StreamTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamTokens").field("ttl", &self.ttl).finish_non_exhaustive()
+    }
+}
+
+impl StreamTokens {
+    /// Uses [`AccessConfig::token_secret`], or a random key which invalidates the tokens on restart.
+    pub fn new(config: &AccessConfig) -> Self {
+        let rng = SystemRandom::new();
+        let key = if config.token_secret.is_empty() {
+            hmac::Key::generate(hmac::HMAC_SHA256, &rng).expect("Failed to generate stream token key")
+        } else {
+            hmac::Key::new(hmac::HMAC_SHA256, config.token_secret.as_bytes())
+        };
+        Self {
+            key,
+            ttl: config.token_ttl,
+            rng,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration { self.ttl }
+
+    pub fn issue(&self, user_id: i64) -> String {
+        let expires = (SystemTime::now() + self.ttl)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce).expect("Failed to generate stream token nonce");
+        let payload = format!("{}.{}.{}", expires, user_id, URL_SAFE_NO_PAD.encode(nonce));
+        let signature = hmac::sign(&self.key, payload.as_bytes());
+        format!("{}.{}", payload, URL_SAFE_NO_PAD.encode(signature.as_ref()))
+    }
+
+    pub fn verify(&self, token: &str) -> Result<TokenClaims, TokenError> {
+        let (payload, signature) = token.rsplit_once('.').ok_or(TokenError::Malformed)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| TokenError::Malformed)?;
+        hmac::verify(&self.key, payload.as_bytes(), &signature).map_err(|_| TokenError::BadSignature)?;
+
+        let mut parts = payload.split('.');
+        let expires: u64 = parts.next().and_then(|s| s.parse().ok()).ok_or(TokenError::Malformed)?;
+        let user_id: i64 = parts.next().and_then(|s| s.parse().ok()).ok_or(TokenError::Malformed)?;
+        let expires_at = UNIX_EPOCH + Duration::from_secs(expires);
+        if expires_at <= SystemTime::now() {
+            return Err(TokenError::Expired);
+        }
+        Ok(TokenClaims { user_id, expires_at })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(secret: &str, ttl: Duration) -> StreamTokens {
+        StreamTokens::new(&AccessConfig {
+            token_secret: secret.to_string(),
+            token_ttl: ttl,
+            ..AccessConfig::default()
+        })
+    }
+
+    #[test]
+    fn round_trip() {
+        let tokens = tokens("secret", Duration::from_secs(60));
+        let claims = tokens.verify(&tokens.issue(42)).unwrap();
+        assert_eq!(claims.user_id, 42);
+        assert!(claims.expires_at > SystemTime::now());
+    }
+
+    #[test]
+    fn tampered_token() {
+        let tokens = tokens("secret", Duration::from_secs(60));
+        let token = tokens.issue(42);
+        let (expires, rest) = token.split_once('.').unwrap();
+        let (_, rest) = rest.split_once('.').unwrap();
+        let other_user = format!("{expires}.43.{rest}");
+        assert_eq!(tokens.verify(&other_user).unwrap_err(), TokenError::BadSignature);
+
+        let (payload, _) = token.rsplit_once('.').unwrap();
+        let bad_signature = format!("{payload}.{}", URL_SAFE_NO_PAD.encode([0u8; 32]));
+        assert_eq!(tokens.verify(&bad_signature).unwrap_err(), TokenError::BadSignature);
+    }
+
+    #[test]
+    fn expired_token() {
+        let tokens = tokens("secret", Duration::ZERO);
+        assert_eq!(tokens.verify(&tokens.issue(42)).unwrap_err(), TokenError::Expired);
+    }
+
+    #[test]
+    fn malformed_token() {
+        let tokens = tokens("secret", Duration::from_secs(60));
+        assert_eq!(tokens.verify("").unwrap_err(), TokenError::Malformed);
+        assert_eq!(tokens.verify("no-dots").unwrap_err(), TokenError::Malformed);
+        assert_eq!(tokens.verify("1.2.3.not base64!").unwrap_err(), TokenError::Malformed);
+    }
+
+    #[test]
+    fn token_of_another_key() {
+        let issued = tokens("secret", Duration::from_secs(60)).issue(42);
+        let other = tokens("other secret", Duration::from_secs(60));
+        assert_eq!(other.verify(&issued).unwrap_err(), TokenError::BadSignature);
+        // a random key is used without a configured secret
+        assert_eq!(tokens("", Duration::from_secs(60)).verify(&issued).unwrap_err(), TokenError::BadSignature);
+    }
+}
@@ -0,0 +1,280 @@
+use std::io::{self, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+/// Size of the headers in front of the first frame.
+pub const HEADER_SIZE: u64 = 224;
+/// `idx1` entry, `00dc` chunk header and padding of every frame.
+pub const FRAME_OVERHEAD: u64 = 16 + 8 + 1;
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+/// Writes JPEG frames as an MJPEG AVI file, playable without any transcoding.
+///
+/// The sizes, the frame count and the frame rate are only known at the end, the headers are
+/// written with placeholders and patched by [`AviWriter::finish`].
+pub struct AviWriter<W: Write + Seek> {
+    out: W,
+    width: u16,
+    height: u16,
+    /// Size and offset (relative to the `movi` fourcc) of every frame, for the index.
+    index: Vec<(u32, u32)>,
+    movi_size: u32,
+    max_frame_size: u32,
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    pub fn new(mut out: W, width: u16, height: u16) -> io::Result<Self> {
+        let (w, h) = (u32::from(width), u32::from(height));
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(b"RIFF");
+        put_u32(&mut header, 0);
+        header.extend_from_slice(b"AVI LIST");
+        put_u32(&mut header, 192);
+        header.extend_from_slice(b"hdrlavih");
+        put_u32(&mut header, 56);
+        // microseconds per frame, max bytes per second, padding, flags, total frames,
+        // initial frames, streams, suggested buffer size, width, height, 4 reserved
+        for value in [0, 0, 0, AVIF_HASINDEX, 0, 0, 1, 0, w, h, 0, 0, 0, 0] {
+            put_u32(&mut header, value);
+        }
+        header.extend_from_slice(b"LIST");
+        put_u32(&mut header, 116);
+        header.extend_from_slice(b"strlstrh");
+        put_u32(&mut header, 56);
+        header.extend_from_slice(b"vidsMJPG");
+        // flags, priority and language, initial frames, scale, rate, start, length,
+        // suggested buffer size, quality, sample size
+        for value in [0, 0, 0, 0, 0, 0, 0, 0, u32::MAX, 0] {
+            put_u32(&mut header, value);
+        }
+        // frame rectangle
+        for value in [0, 0, width, height] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        header.extend_from_slice(b"strf");
+        put_u32(&mut header, 40);
+        // BITMAPINFOHEADER
+        for value in [40, w, h] {
+            put_u32(&mut header, value);
+        }
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&24u16.to_le_bytes());
+        header.extend_from_slice(b"MJPG");
+        for value in [w * h * 3, 0, 0, 0, 0] {
+            put_u32(&mut header, value);
+        }
+        header.extend_from_slice(b"LIST");
+        put_u32(&mut header, 0);
+        header.extend_from_slice(b"movi");
+        debug_assert_eq!(header.len() as u64, HEADER_SIZE);
+        out.write_all(&header)?;
+        Ok(Self {
+            out,
+            width,
+            height,
+            index: Vec::new(),
+            movi_size: 4,
+            max_frame_size: 0,
+        })
+    }
+
+    pub fn write_frame(&mut self, jpeg: &[u8]) -> io::Result<()> {
+        let size = u32::try_from(jpeg.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
+        let padded = size + size % 2;
+        let movi_size = self
+            .movi_size
+            .checked_add(8 + padded)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "AVI file too large"))?;
+        self.out.write_all(b"00dc")?;
+        self.out.write_all(&size.to_le_bytes())?;
+        self.out.write_all(jpeg)?;
+        if padded > size {
+            self.out.write_all(&[0])?;
+        }
+        self.index.push((size, self.movi_size));
+        self.movi_size = movi_size;
+        self.max_frame_size = self.max_frame_size.max(size);
+        Ok(())
+    }
+
+    pub fn frames(&self) -> usize { self.index.len() }
+
+    pub fn dimensions(&self) -> (u16, u16) { (self.width, self.height) }
+
+    /// Writes the index and the final headers, the frames are spread evenly over `duration`.
+    pub fn finish(mut self, duration: Duration) -> io::Result<W> {
+        let frames = u32::try_from(self.index.len()).unwrap_or(u32::MAX);
+        let mut index = Vec::with_capacity(self.index.len() * 16);
+        for &(size, offset) in &self.index {
+            index.extend_from_slice(b"00dc");
+            for value in [AVIIF_KEYFRAME, offset, size] {
+                put_u32(&mut index, value);
+            }
+        }
+        self.out.write_all(b"idx1")?;
+        self.out
+            .write_all(&u32::try_from(index.len()).unwrap_or(u32::MAX).to_le_bytes())?;
+        self.out.write_all(&index)?;
+
+        let frame_micros = if frames == 0 {
+            0
+        } else {
+            u32::try_from(duration.as_micros() / u128::from(frames)).unwrap_or(u32::MAX)
+        };
+        // frame rate as rate / scale
+        let scale = 1000;
+        let rate = if frame_micros == 0 {
+            scale
+        } else {
+            u32::try_from(1_000_000_000 / u64::from(frame_micros)).unwrap_or(u32::MAX)
+        };
+        let bytes_per_sec =
+            u32::try_from(u64::from(self.max_frame_size) * u64::from(rate) / u64::from(scale)).unwrap_or(u32::MAX);
+        let riff_size = u32::try_from(HEADER_SIZE - 8 + u64::from(self.movi_size) - 4 + 8 + index.len() as u64)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "AVI file too large"))?;
+
+        for (offset, value) in [
+            (4, riff_size),
+            (32, frame_micros),
+            (36, bytes_per_sec),
+            (48, frames),
+            (60, self.max_frame_size),
+            (128, scale),
+            (132, rate),
+            (140, frames),
+            (144, self.max_frame_size),
+            (216, self.movi_size),
+        ] {
+            self.out.seek(SeekFrom::Start(offset))?;
+            self.out.write_all(&value.to_le_bytes())?;
+        }
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) { buf.extend_from_slice(&value.to_le_bytes()); }
+
+/// Reads the width and height of a JPEG image from its start of frame marker.
+pub fn jpeg_dimensions(jpeg: &[u8]) -> Option<(u16, u16)> {
+    if jpeg.get(.. 2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut pos = 2;
+    loop {
+        while *jpeg.get(pos)? != 0xFF {
+            pos += 1;
+        }
+        while *jpeg.get(pos)? == 0xFF {
+            pos += 1;
+        }
+        let marker = *jpeg.get(pos)?;
+        pos += 1;
+        match marker {
+            // standalone markers
+            0x01 | 0xD0 ..= 0xD7 => continue,
+            // SOF0 to SOF15, except DHT, JPG and DAC
+            0xC0 ..= 0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                let segment = jpeg.get(pos + 3 .. pos + 7)?;
+                let height = u16::from_be_bytes([segment[0], segment[1]]);
+                let width = u16::from_be_bytes([segment[2], segment[3]]);
+                return Some((width, height));
+            }
+            0xD9 | 0xDA => return None,
+            _ => {
+                let length = u16::from_be_bytes([*jpeg.get(pos)?, *jpeg.get(pos + 1)?]);
+                pos += usize::from(length);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Offset of the `movi` fourcc, the `idx1` offsets are relative to it.
+    const MOVI: usize = HEADER_SIZE as usize - 4;
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 { u32::from_le_bytes(data[offset .. offset + 4].try_into().unwrap()) }
+
+    fn write_avi(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut avi = AviWriter::new(Cursor::new(Vec::new()), 640, 480).unwrap();
+        for frame in frames {
+            avi.write_frame(frame).unwrap();
+        }
+        avi.finish(Duration::from_secs(1)).unwrap().into_inner()
+    }
+
+    #[test]
+    fn headers_match_the_content() {
+        let frames = vec![vec![1; 10], vec![2; 7], vec![3; 4]];
+        let avi = write_avi(&frames);
+        assert_eq!(&avi[.. 4], b"RIFF");
+        assert_eq!(u32_at(&avi, 4) as usize, avi.len() - 8);
+        assert_eq!(&avi[212 .. 216], b"LIST");
+        assert_eq!(&avi[MOVI .. MOVI + 4], b"movi");
+        let movi_size = u32_at(&avi, 216) as usize;
+        let idx1 = MOVI + movi_size;
+        assert_eq!(&avi[idx1 .. idx1 + 4], b"idx1");
+        assert_eq!(u32_at(&avi, idx1 + 4) as usize, frames.len() * 16);
+        assert_eq!(idx1 + 8 + frames.len() * 16, avi.len());
+        // total frames in the main and the stream header
+        assert_eq!(u32_at(&avi, 48), 3);
+        assert_eq!(u32_at(&avi, 140), 3);
+    }
+
+    #[test]
+    fn index_points_at_frames() {
+        let frames = vec![vec![1; 10], vec![2; 7], vec![3; 4]];
+        let avi = write_avi(&frames);
+        let idx1 = MOVI + u32_at(&avi, 216) as usize;
+        for (i, frame) in frames.iter().enumerate() {
+            let entry = idx1 + 8 + i * 16;
+            assert_eq!(&avi[entry .. entry + 4], b"00dc");
+            let chunk = MOVI + u32_at(&avi, entry + 8) as usize;
+            let size = u32_at(&avi, entry + 12) as usize;
+            assert_eq!(&avi[chunk .. chunk + 4], b"00dc");
+            assert_eq!(u32_at(&avi, chunk + 4) as usize, size);
+            assert_eq!(&avi[chunk + 8 .. chunk + 8 + size], frame.as_slice());
+        }
+    }
+
+    #[test]
+    fn odd_frames_are_padded() {
+        let avi = write_avi(&[vec![1; 7], vec![2; 4]]);
+        let first = HEADER_SIZE as usize;
+        assert_eq!(u32_at(&avi, first + 4), 7);
+        // one zero byte keeps the next chunk at an even offset
+        assert_eq!(avi[first + 8 + 7], 0);
+        let second = first + 8 + 8;
+        assert_eq!(&avi[second .. second + 4], b"00dc");
+        assert_eq!(u32_at(&avi, 216), 4 + 16 + 12);
+        assert_eq!(avi.len() as u64, HEADER_SIZE + 16 + 12 + 8 + 2 * 16);
+    }
+
+    #[test]
+    fn overhead_matches_the_writer() {
+        let frames = vec![vec![1; 9], vec![2; 9]];
+        let avi = write_avi(&frames);
+        let expected = HEADER_SIZE + 8 + frames.iter().map(|frame| frame.len() as u64 + FRAME_OVERHEAD).sum::<u64>();
+        // odd frames take the padding byte counted by FRAME_OVERHEAD
+        assert_eq!(avi.len() as u64, expected);
+    }
+
+    #[test]
+    fn jpeg_dimensions_of_start_of_frame() {
+        // SOI, APP0 with two bytes of data, SOF0 with height 480 and width 640
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01, 0xE0, 0x02, 0x80, 0x03,
+        ];
+        assert_eq!(jpeg_dimensions(&jpeg), Some((640, 480)));
+        assert_eq!(jpeg_dimensions(&jpeg[.. 12]), None);
+        assert_eq!(jpeg_dimensions(b"not a jpeg"), None);
+        // a DHT segment is not a start of frame
+        let dht = [0xFF, 0xD8, 0xFF, 0xC4, 0x00, 0x02, 0xFF, 0xD9];
+        assert_eq!(jpeg_dimensions(&dht), None);
+    }
+}
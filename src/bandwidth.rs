@@ -0,0 +1,152 @@
+use crate::config::{BandwidthConfig, ExhaustedAction};
+use crate::core::{utc_date, write_file_atomic};
+use roboplc::locking::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+pub const BYTES_PER_MB: u64 = 1024 * 1024;
+
+/// Bytes sent to remote viewers in a calendar month (UTC).
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct MonthUsage {
+    pub bytes: u64,
+    /// Highest budget threshold the admin has been warned about.
+    pub warned_percent: u8,
+}
+
+/// Content of the bandwidth file, months are keyed as `2024-09`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BandwidthFile {
+    #[serde(default)]
+    months: BTreeMap<String, MonthUsage>,
+}
+
+#[derive(Debug)]
+struct MeterState {
+    file: BandwidthFile,
+    dirty: bool,
+}
+
+impl MeterState {
+    fn current(&mut self) -> &mut MonthUsage { self.file.months.entry(month_key(SystemTime::now())).or_default() }
+}
+
+/// A budget threshold crossed since the last check.
+#[derive(Debug, Clone, Copy)]
+pub struct BudgetWarning {
+    pub percent: u8,
+    pub used: u64,
+    pub budget: u64,
+}
+
+/// Counts bytes streamed to remote viewers and persists the totals per calendar month.
+#[derive(Debug)]
+pub struct BandwidthMeter {
+    path: PathBuf,
+    config: BandwidthConfig,
+    state: Mutex<MeterState>,
+}
+
+impl BandwidthMeter {
+    /// Loads the totals from `path`, a missing or broken file starts the counters from zero.
+    pub fn open(path: PathBuf, config: BandwidthConfig) -> Self {
+        let file = match Self::load(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Failed to read bandwidth totals from {}: {}", path.display(), e);
+                BandwidthFile::default()
+            }
+        };
+        Self {
+            path,
+            config,
+            state: Mutex::new(MeterState { file, dirty: false }),
+        }
+    }
+
+    fn load(path: &Path) -> Result<BandwidthFile, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(BandwidthFile::default());
+        }
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Writes the totals if they have changed since the last save.
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let content = {
+            let mut state = self.state.lock();
+            if !state.dirty {
+                return Ok(());
+            }
+            state.dirty = false;
+            toml::to_string(&state.file)?
+        };
+        write_file_atomic(&self.path, &content)?;
+        Ok(())
+    }
+
+    pub fn add(&self, bytes: usize) {
+        let mut state = self.state.lock();
+        let usage = state.current();
+        usage.bytes = usage.bytes.saturating_add(bytes as u64);
+        state.dirty = true;
+    }
+
+    /// Usage of the current month.
+    pub fn usage(&self) -> MonthUsage { *self.state.lock().current() }
+
+    /// Monthly budget in bytes, zero if unlimited.
+    pub fn budget(&self) -> u64 { self.config.budget_mb.saturating_mul(BYTES_PER_MB) }
+
+    pub fn is_exhausted(&self) -> bool {
+        let budget = self.budget();
+        budget > 0 && self.usage().bytes >= budget
+    }
+
+    /// Remote streaming is refused when the budget is exhausted.
+    pub fn is_refused(&self) -> bool { self.config.exhausted == ExhaustedAction::Refuse && self.is_exhausted() }
+
+    /// Frame rate limit of remote viewers, set in the low-rate mode after the budget is exhausted.
+    pub fn remote_fps_limit(&self) -> Option<u32> {
+        (self.config.exhausted == ExhaustedAction::LowRate && self.is_exhausted()).then_some(self.config.low_rate_fps)
+    }
+
+    /// Returns the highest threshold crossed since the last call, 100% is reported on exhaustion.
+    pub fn check_budget(&self) -> Option<BudgetWarning> {
+        let budget = self.budget();
+        if budget == 0 {
+            return None;
+        }
+        let mut state = self.state.lock();
+        let usage = state.current();
+        let percent = usage.bytes.saturating_mul(100) / budget;
+        let crossed = self
+            .config
+            .warn_percent
+            .iter()
+            .copied()
+            .chain([100])
+            .filter(|&threshold| u64::from(threshold) <= percent && threshold > usage.warned_percent)
+            .max()?;
+        usage.warned_percent = crossed;
+        let used = usage.bytes;
+        state.dirty = true;
+        info!(percent = crossed, used, budget, "Bandwidth budget threshold crossed");
+        Some(BudgetWarning {
+            percent: crossed,
+            used,
+            budget,
+        })
+    }
+}
+
+pub fn format_mb(bytes: u64) -> String { format!("{:.1} MB", bytes as f64 / BYTES_PER_MB as f64) }
+
+/// Year and month (UTC) of `time`, like `2024-09`.
+fn month_key(time: SystemTime) -> String {
+    let (year, month, _) = utc_date(time);
+    format!("{year:04}-{month:02}")
+}
@@ -0,0 +1,230 @@
+use crate::avi::{jpeg_dimensions, AviWriter, FRAME_OVERHEAD, HEADER_SIZE};
+use crate::core::WorkerMessage;
+use crate::preroll::PrerollBuffer;
+use roboplc::hub::Hub;
+use std::fmt;
+use std::io::{self, Cursor};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// How often the hub queue is polled while recording.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Numbers the hub clients of the recordings, hub client names must be unique.
+static RECORDINGS: AtomicU64 = AtomicU64::new(0);
+
+/// JPEG frames of a video clip, thinned out to keep the AVI file under a size limit.
+///
+/// Once the limit is reached, every other frame is dropped and only every second one of the
+/// following frames is kept, which halves the frame rate instead of cutting the clip short.
+#[derive(Debug)]
+pub struct Clip {
+    max_bytes: u64,
+    frames: Vec<Arc<Vec<u8>>>,
+    /// Size of the AVI file with the current frames.
+    bytes: u64,
+    /// Every `stride`-th offered frame is kept.
+    stride: usize,
+    offered: usize,
+    duration: Duration,
+    preroll: Duration,
+}
+
+impl Clip {
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            frames: Vec::new(),
+            bytes: HEADER_SIZE,
+            stride: 1,
+            offered: 0,
+            duration: Duration::ZERO,
+            preroll: Duration::ZERO,
+        }
+    }
+
+    /// Adds a frame, returns false if it has been dropped to lower the frame rate.
+    pub fn push(&mut self, frame: Arc<Vec<u8>>) -> bool {
+        let keep = self.offered.is_multiple_of(self.stride);
+        self.offered += 1;
+        if !keep {
+            return false;
+        }
+        self.bytes += frame.len() as u64 + FRAME_OVERHEAD;
+        self.frames.push(frame);
+        while self.bytes > self.max_bytes && self.frames.len() > 1 {
+            self.stride *= 2;
+            let mut i = 0;
+            self.frames.retain(|_| {
+                i += 1;
+                i % 2 == 1
+            });
+            self.bytes = HEADER_SIZE
+                + self
+                    .frames
+                    .iter()
+                    .map(|frame| frame.len() as u64 + FRAME_OVERHEAD)
+                    .sum::<u64>();
+        }
+        true
+    }
+
+    pub fn frames(&self) -> usize { self.frames.len() }
+
+    /// Size of the AVI file.
+    pub fn bytes(&self) -> u64 { self.bytes }
+
+    /// Recorded length, the frames are spread evenly over it.
+    pub fn duration(&self) -> Duration { self.duration }
+
+    /// Part of [`Clip::duration`] taken from the pre-roll buffer.
+    pub fn preroll(&self) -> Duration { self.preroll }
+
+    pub fn fps(&self) -> f64 {
+        if self.duration.is_zero() {
+            0.0
+        } else {
+            self.frames.len() as f64 / self.duration.as_secs_f64()
+        }
+    }
+
+    /// Frame size of the clip, taken from its first frame.
+    pub fn dimensions(&self) -> Option<(u16, u16)> { self.frames.first().and_then(|frame| jpeg_dimensions(frame)) }
+
+    /// Encodes the clip as an MJPEG AVI file.
+    pub fn to_avi(&self) -> io::Result<Vec<u8>> {
+        let (width, height) = self
+            .dimensions()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no JPEG frames"))?;
+        let capacity = usize::try_from(self.bytes).unwrap_or_default();
+        let mut avi = AviWriter::new(Cursor::new(Vec::with_capacity(capacity)), width, height)?;
+        for frame in &self.frames {
+            avi.write_frame(frame)?;
+        }
+        Ok(avi.finish(self.duration)?.into_inner())
+    }
+}
+
+#[derive(Debug)]
+pub enum ClipError {
+    Hub(roboplc::Error),
+    /// The camera sent no frames within the timeout.
+    NoFrames {
+        camera: String,
+        timeout: Duration,
+    },
+}
+
+impl fmt::Display for ClipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipError::Hub(e) => write!(f, "failed to subscribe to the frames: {e}"),
+            ClipError::NoFrames { camera, timeout } => write!(
+                f,
+                "camera {camera} sent no frames within {:.1} s, it may be disconnected or busy",
+                timeout.as_secs_f64()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ClipError {}
+
+/// Records the frames of a camera from the hub for `length` after the frames in the pre-roll
+/// buffer, blocks meanwhile.
+///
+/// Without pre-roll, the recording starts with the first frame, which must arrive within `timeout`.
+pub fn record(
+    hub: &Hub<WorkerMessage>,
+    preroll: &PrerollBuffer,
+    camera: &str,
+    length: Duration,
+    timeout: Duration,
+    max_bytes: u64,
+) -> Result<Clip, ClipError> {
+    let name = format!("clip.{}", RECORDINGS.fetch_add(1, Ordering::Relaxed));
+    let owned_camera = camera.to_string();
+    let client = hub
+        .register(&name, move |msg: &WorkerMessage| msg.is_frame_of(&owned_camera))
+        .map_err(ClipError::Hub)?;
+
+    let mut clip = Clip::new(max_bytes);
+    let buffered = preroll.frames(camera);
+    let waiting = Instant::now();
+    let mut started = buffered.first().map(|_| waiting);
+    clip.preroll = buffered
+        .first()
+        .map_or(Duration::ZERO, |first| waiting.duration_since(first.received));
+    // the newest buffered frame may also be waiting in the queue of the client
+    let mut last_buffered = buffered.last().map(|frame| frame.data.clone());
+    for frame in buffered {
+        clip.push(frame.data);
+    }
+    loop {
+        let now = Instant::now();
+        let deadline = match started {
+            Some(started) => started + length,
+            None => waiting + timeout,
+        };
+        if now >= deadline {
+            break;
+        }
+        match client.try_recv() {
+            Ok(WorkerMessage::Frame(frame)) => {
+                if last_buffered.take().is_some_and(|last| *last == frame.data) {
+                    continue;
+                }
+                started.get_or_insert(now);
+                clip.push(Arc::new(frame.data));
+            }
+            Ok(_) => {}
+            Err(_) => sleep(POLL_INTERVAL.min(deadline - now)),
+        }
+    }
+    if started.is_none() {
+        return Err(ClipError::NoFrames {
+            camera: camera.to_string(),
+            timeout,
+        });
+    }
+    clip.duration = clip.preroll + length;
+    Ok(clip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> Arc<Vec<u8>> { Arc::new(vec![0; 100]) }
+
+    #[test]
+    fn push_halves_the_frame_rate_at_the_limit() {
+        let frame_bytes = 100 + FRAME_OVERHEAD;
+        let mut clip = Clip::new(HEADER_SIZE + 4 * frame_bytes);
+        for _ in 0 .. 4 {
+            assert!(clip.push(frame()));
+        }
+        assert_eq!(clip.frames(), 4);
+
+        // the fifth frame exceeds the limit, every other frame is dropped
+        assert!(clip.push(frame()));
+        assert_eq!(clip.frames(), 3);
+        assert_eq!(clip.bytes(), HEADER_SIZE + 3 * frame_bytes);
+
+        // only every second of the following frames is kept
+        assert!(!clip.push(frame()));
+        assert!(clip.push(frame()));
+        assert!(!clip.push(frame()));
+        assert_eq!(clip.frames(), 4);
+        assert!(clip.bytes() <= HEADER_SIZE + 4 * frame_bytes);
+    }
+
+    #[test]
+    fn push_keeps_a_single_oversized_frame() {
+        let mut clip = Clip::new(HEADER_SIZE);
+        assert!(clip.push(frame()));
+        assert_eq!(clip.frames(), 1);
+    }
+}
@@ -0,0 +1,1637 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+const BUF_COUNT: u32 = 20;
+const DEFAULT_CAMERA_WIDTH: u32 = 640;
+const DEFAULT_CAMERA_HEIGHT: u32 = 480;
+const DEFAULT_CAMERA_DEV_IDX: u8 = 0;
+const DEFAULT_CAMERA_INTERVAL: (u32, u32) = (1, 30);
+const DEFAULT_CAMERA_FOURCC: [u8; 4] = *b"MJPG";
+const DEFAULT_SERVER_ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 8080);
+const DEFAULT_MJPEG_MAX_FPS: u32 = 10;
+const DEFAULT_PHOTO_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_PHOTO_SKIP_FRAMES: usize = 0;
+/// Telegram albums hold up to 10 photos.
+const MAX_BURST_FRAMES: usize = 10;
+const DEFAULT_BURST_MIN_INTERVAL: Duration = Duration::from_millis(200);
+/// Keeps a `/burst` from blocking the chat for long.
+pub const MAX_BURST_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_CLIP_MAX_LENGTH: Duration = Duration::from_secs(30);
+/// Bots may upload files up to 50 MB, some room is left for the request.
+const DEFAULT_CLIP_MAX_MB: u64 = 48;
+const MAX_CLIP_MB: u64 = 50;
+const DEFAULT_TUNNEL_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_DATA_DIR: &str = "/var/lib/inst-upd";
+const DEFAULT_BANDWIDTH_WARN_PERCENT: [u8; 3] = [50, 80, 95];
+const DEFAULT_LOW_RATE_FPS: u32 = 1;
+const DEFAULT_STREAM_TOKEN_TTL: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_NOTIFY_DIGEST_WINDOW: Duration = Duration::from_secs(60);
+const DEFAULT_RECORDING_SEGMENT_LENGTH: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_RECORDING_MAX_FPS: u32 = 5;
+const DEFAULT_RECORDING_MAX_AGE_HOURS: u64 = 72;
+const DEFAULT_RECORDING_MAX_SIZE_MB: u64 = 2048;
+const DEFAULT_RECORDING_POST_EVENT: Duration = Duration::from_secs(10);
+const DEFAULT_PREROLL_LENGTH: Duration = Duration::from_secs(5);
+const DEFAULT_PREROLL_MAX_MB: u64 = 16;
+const DEFAULT_MOTION_SENSITIVITY: u8 = 90;
+const DEFAULT_MOTION_MIN_AREA_PERCENT: f64 = 2.0;
+const DEFAULT_MOTION_COOLDOWN: Duration = Duration::from_secs(60);
+const DEFAULT_MOTION_MAX_FPS: u32 = 2;
+/// Directory of the recordings under [`Config::data_dir`] unless set.
+const DEFAULT_RECORDING_DIR: &str = "recordings";
+/// UTC offsets range from -12:00 to +14:00.
+const MAX_UTC_OFFSET_MINUTES: i32 = 14 * 60;
+pub const DEFAULT_CAMERA_NAME: &str = "main";
+/// Config file read when `CONFIG_FILE` is not set, skipped if it does not exist.
+pub const DEFAULT_CONFIG_FILE: &str = "/etc/roboplc/program.toml";
+
+/// Where [`crate::workers::DetectorVideo`] takes its frames from.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CameraSource {
+    /// V4L2 device `/dev/video{dev_idx}`.
+    #[default]
+    V4l2,
+    /// Synthetic test pattern, no hardware required.
+    #[serde(rename = "test")]
+    TestPattern,
+    /// Replay of a JPEG directory, a single JPEG or an MJPEG file.
+    Replay(PathBuf),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraConfig {
+    pub name: String,
+    pub source: CameraSource,
+    /// Frame interval as a fraction of a second, `[1, 30]` is 30 FPS.
+    pub interval: (u32, u32),
+    pub width: u32,
+    pub height: u32,
+    #[serde(with = "fourcc")]
+    pub fourcc: [u8; 4],
+    pub buf_size: u32,
+    pub dev_idx: u8,
+    /// Parts of the picture the motion detection watches or ignores.
+    pub zones: Vec<MotionZone>,
+}
+
+impl CameraConfig {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            source: CameraSource::default(),
+            interval: DEFAULT_CAMERA_INTERVAL,
+            width: DEFAULT_CAMERA_WIDTH,
+            height: DEFAULT_CAMERA_HEIGHT,
+            fourcc: DEFAULT_CAMERA_FOURCC,
+            buf_size: BUF_COUNT,
+            dev_idx: DEFAULT_CAMERA_DEV_IDX,
+            zones: Vec::new(),
+        }
+    }
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self { Self::new(DEFAULT_CAMERA_NAME) }
+}
+
+/// Access level of a bot user, a higher role has all the permissions of the lower ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Operator,
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "viewer" => Ok(Role::Viewer),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Viewer => write!(f, "viewer"),
+            Role::Operator => write!(f, "operator"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelegramConfig {
+    pub token: String,
+    pub admin_user_id: i64,
+    /// Users with the [`Role::Viewer`] role.
+    pub allowed_user_ids: Vec<i64>,
+    /// Users with the [`Role::Operator`] role.
+    pub operator_user_ids: Vec<i64>,
+    /// How long `/photo` waits for a fresh frame.
+    #[serde(rename = "photo_timeout_ms", with = "duration_ms")]
+    pub photo_timeout: Duration,
+    /// Frames skipped before `/photo` takes one, lets auto-exposure settle.
+    pub photo_skip_frames: usize,
+    /// Upper limit of the frames taken by `/burst`.
+    pub burst_max_frames: usize,
+    /// Lower limit of the spacing of the `/burst` frames.
+    #[serde(rename = "burst_min_interval_ms", with = "duration_ms")]
+    pub burst_min_interval: Duration,
+    /// Upper limit of the length of `/clip`.
+    #[serde(rename = "clip_max_secs", with = "duration_secs")]
+    pub clip_max_length: Duration,
+    /// Size limit of the `/clip` files, the frame rate is lowered to stay below it.
+    pub clip_max_mb: u64,
+}
+
+impl TelegramConfig {
+    /// Role of a user configured in the settings, `None` for unknown users.
+    pub fn role_of(&self, user_id: i64) -> Option<Role> {
+        if user_id == self.admin_user_id {
+            Some(Role::Admin)
+        } else if self.operator_user_ids.contains(&user_id) {
+            Some(Role::Operator)
+        } else if self.allowed_user_ids.contains(&user_id) {
+            Some(Role::Viewer)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for TelegramConfig {
+    fn default() -> Self {
+        Self {
+            token: String::new(),
+            admin_user_id: 0,
+            allowed_user_ids: Vec::new(),
+            operator_user_ids: Vec::new(),
+            photo_timeout: DEFAULT_PHOTO_TIMEOUT,
+            photo_skip_frames: DEFAULT_PHOTO_SKIP_FRAMES,
+            burst_max_frames: MAX_BURST_FRAMES,
+            burst_min_interval: DEFAULT_BURST_MIN_INTERVAL,
+            clip_max_length: DEFAULT_CLIP_MAX_LENGTH,
+            clip_max_mb: DEFAULT_CLIP_MAX_MB,
+        }
+    }
+}
+
+/// What happens to remote streaming once the monthly budget is used up.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExhaustedAction {
+    /// `/getvideo` is refused and the running tunnel is stopped.
+    #[default]
+    Refuse,
+    /// Remote viewers get `low_rate_fps` frames per second.
+    LowRate,
+}
+
+impl FromStr for ExhaustedAction {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "refuse" => Ok(ExhaustedAction::Refuse),
+            "low_rate" => Ok(ExhaustedAction::LowRate),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Monthly data budget of remote streaming, see [`crate::bandwidth::BandwidthMeter`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BandwidthConfig {
+    /// Budget per calendar month in megabytes, zero means unlimited.
+    pub budget_mb: u64,
+    /// Usage levels in percent of the budget the admin is warned about.
+    pub warn_percent: Vec<u8>,
+    pub exhausted: ExhaustedAction,
+    pub low_rate_fps: u32,
+}
+
+impl Default for BandwidthConfig {
+    fn default() -> Self {
+        Self {
+            budget_mb: 0,
+            warn_percent: DEFAULT_BANDWIDTH_WARN_PERCENT.to_vec(),
+            exhausted: ExhaustedAction::default(),
+            low_rate_fps: DEFAULT_LOW_RATE_FPS,
+        }
+    }
+}
+
+/// Protection of the stream endpoints, see [`crate::access::StreamTokens`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessConfig {
+    /// Requests through the tunnel need the signed token sent by `/getvideo`.
+    pub require_token: bool,
+    #[serde(rename = "token_ttl_secs", with = "duration_secs")]
+    pub token_ttl: Duration,
+    /// HMAC key of the tokens, a random key is used if empty (tokens do not survive a restart).
+    pub token_secret: String,
+}
+
+impl Default for AccessConfig {
+    fn default() -> Self {
+        Self {
+            require_token: true,
+            token_ttl: DEFAULT_STREAM_TOKEN_TTL,
+            token_secret: String::new(),
+        }
+    }
+}
+
+/// Bot activity the admin can be notified about, see [`crate::notify::Notifier`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyEvent {
+    /// A user ran a command like `/photo` or `/getvideo`.
+    Command,
+    /// A user tried a command their role does not allow.
+    Unauthorized,
+    InvalidCommand,
+    /// The tunnel was started or stopped.
+    Tunnel,
+    Error,
+}
+
+impl NotifyEvent {
+    pub const ALL: [NotifyEvent; 5] = [
+        NotifyEvent::Command,
+        NotifyEvent::Unauthorized,
+        NotifyEvent::InvalidCommand,
+        NotifyEvent::Tunnel,
+        NotifyEvent::Error,
+    ];
+    pub const EXPECTED: &'static str = "command, unauthorized, invalid_command, tunnel, error";
+}
+
+impl FromStr for NotifyEvent {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "command" => Ok(NotifyEvent::Command),
+            "unauthorized" => Ok(NotifyEvent::Unauthorized),
+            "invalid_command" => Ok(NotifyEvent::InvalidCommand),
+            "tunnel" => Ok(NotifyEvent::Tunnel),
+            "error" => Ok(NotifyEvent::Error),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for NotifyEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifyEvent::Command => write!(f, "command"),
+            NotifyEvent::Unauthorized => write!(f, "unauthorized"),
+            NotifyEvent::InvalidCommand => write!(f, "invalid_command"),
+            NotifyEvent::Tunnel => write!(f, "tunnel"),
+            NotifyEvent::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Daily period without notifications, written as `22:00-07:00` in the local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct QuietHours {
+    /// Minutes since midnight.
+    start: u16,
+    end: u16,
+}
+
+impl QuietHours {
+    pub const EXPECTED: &'static str = "period like 22:00-07:00";
+
+    /// Checks a time given in minutes since midnight, the period may span midnight.
+    pub fn contains(&self, minute: u16) -> bool {
+        if self.start <= self.end {
+            (self.start .. self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse_time = |time: &str| {
+            let (hours, minutes) = time.trim().split_once(':').ok_or(())?;
+            let hours: u16 = hours.parse().map_err(|_| ())?;
+            let minutes: u16 = minutes.parse().map_err(|_| ())?;
+            if hours < 24 && minutes < 60 {
+                Ok(hours * 60 + minutes)
+            } else {
+                Err(())
+            }
+        };
+        let (start, end) = value.split_once('-').ok_or(())?;
+        Ok(Self {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+impl TryFrom<String> for QuietHours {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse().map_err(|()| format!("expected a {}", Self::EXPECTED))
+    }
+}
+
+impl From<QuietHours> for String {
+    fn from(value: QuietHours) -> Self { value.to_string() }
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+/// Notifications of the admin about bot activity, see [`crate::notify::Notifier`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// Events the admin is notified about.
+    pub events: Vec<NotifyEvent>,
+    /// Notify the admin about their own commands too.
+    pub own_actions: bool,
+    /// Notifications are held during this period and sent as a digest when it ends.
+    pub quiet_hours: Option<QuietHours>,
+    /// Local time of the quiet hours in minutes east of UTC, e.g. 120 for UTC+02:00.
+    pub utc_offset_minutes: i32,
+    /// After a notification, further ones within this time are collected into a digest, zero disables.
+    #[serde(rename = "digest_window_secs", with = "duration_secs")]
+    pub digest_window: Duration,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            events: NotifyEvent::ALL.to_vec(),
+            own_actions: false,
+            quiet_hours: None,
+            utc_offset_minutes: 0,
+            digest_window: DEFAULT_NOTIFY_DIGEST_WINDOW,
+        }
+    }
+}
+
+/// What [`crate::workers::RecorderWorker`] writes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingMode {
+    /// All the frames.
+    #[default]
+    Continuous,
+    /// Only the frames around events like `/clip` or motion, starting with the pre-roll.
+    Events,
+}
+
+impl FromStr for RecordingMode {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "continuous" => Ok(RecordingMode::Continuous),
+            "events" => Ok(RecordingMode::Events),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for RecordingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingMode::Continuous => write!(f, "continuous"),
+            RecordingMode::Events => write!(f, "events"),
+        }
+    }
+}
+
+/// Recording of the cameras to disk, see [`crate::workers::RecorderWorker`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    pub enabled: bool,
+    pub mode: RecordingMode,
+    /// In the `events` mode, how long the recording goes on after the last event.
+    #[serde(rename = "post_event_secs", with = "duration_secs")]
+    pub post_event: Duration,
+    /// Directory of the segment files, `recordings` in [`Config::data_dir`] if not set.
+    pub dir: Option<PathBuf>,
+    /// Length of a segment file.
+    #[serde(rename = "segment_secs", with = "duration_secs")]
+    pub segment_length: Duration,
+    /// Upper frame rate of the recording, zero keeps every frame.
+    pub max_fps: u32,
+    /// Segments older than this are deleted, zero keeps them.
+    pub max_age_hours: u64,
+    /// The oldest segments are deleted once all of them take more, zero means unlimited.
+    pub max_size_mb: u64,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: RecordingMode::default(),
+            post_event: DEFAULT_RECORDING_POST_EVENT,
+            dir: None,
+            segment_length: DEFAULT_RECORDING_SEGMENT_LENGTH,
+            max_fps: DEFAULT_RECORDING_MAX_FPS,
+            max_age_hours: DEFAULT_RECORDING_MAX_AGE_HOURS,
+            max_size_mb: DEFAULT_RECORDING_MAX_SIZE_MB,
+        }
+    }
+}
+
+/// In-memory buffer of the last frames, see [`crate::preroll::PrerollBuffer`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrerollConfig {
+    /// Frames of this time before a `/clip` or an event are included, zero disables the buffer.
+    #[serde(rename = "secs", with = "duration_secs")]
+    pub length: Duration,
+    /// Memory limit of the buffers of all cameras.
+    pub max_mb: u64,
+}
+
+impl Default for PrerollConfig {
+    fn default() -> Self {
+        Self {
+            length: DEFAULT_PREROLL_LENGTH,
+            max_mb: DEFAULT_PREROLL_MAX_MB,
+        }
+    }
+}
+
+/// Motion detection, see [`crate::workers::MotionDetector`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MotionConfig {
+    pub enabled: bool,
+    /// Whether the detection runs at start, until `/arm` or `/disarm` is used.
+    pub armed: bool,
+    /// From 1 to 100, the higher the smaller brightness changes of a pixel count as motion.
+    pub sensitivity: u8,
+    /// Share of the picture in percent which must change to trigger an event.
+    pub min_area_percent: f64,
+    /// Time after an event in which further motion of the camera is not reported.
+    #[serde(rename = "cooldown_secs", with = "duration_secs")]
+    pub cooldown: Duration,
+    /// Upper rate of the analyzed frames, decoding them is costly on small boards.
+    pub max_fps: u32,
+    /// Length of the clip sent after the alert photo, zero sends the photo only.
+    #[serde(rename = "clip_secs", with = "duration_secs")]
+    pub clip_length: Duration,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            armed: true,
+            sensitivity: DEFAULT_MOTION_SENSITIVITY,
+            min_area_percent: DEFAULT_MOTION_MIN_AREA_PERCENT,
+            cooldown: DEFAULT_MOTION_COOLDOWN,
+            max_fps: DEFAULT_MOTION_MAX_FPS,
+            clip_length: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZoneKind {
+    /// Only motion inside the detection zones counts, if a camera has any.
+    Detect,
+    /// Motion inside is never counted, e.g. a TV or trees behind a window.
+    Ignore,
+}
+
+impl FromStr for ZoneKind {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "detect" => Ok(ZoneKind::Detect),
+            "ignore" => Ok(ZoneKind::Ignore),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for ZoneKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZoneKind::Detect => write!(f, "detect"),
+            ZoneKind::Ignore => write!(f, "ignore"),
+        }
+    }
+}
+
+/// Part of the picture for the motion detection, in coordinates from 0 to 1 with `0,0` at the top
+/// left corner.
+///
+/// Written as `detect:x,y x,y ...`: two points are the opposite corners of a rectangle, more of
+/// them are the corners of a polygon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct MotionZone {
+    pub kind: ZoneKind,
+    pub points: Vec<(f64, f64)>,
+}
+
+impl MotionZone {
+    pub const EXPECTED: &'static str = "zones like detect:0,0.5 1,1;ignore:0.2,0.1 0.4,0.1 0.3,0.3";
+
+    /// Returns true if the point (in the same coordinates) is inside the zone.
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        match self.points.as_slice() {
+            [(x1, y1), (x2, y2)] => (x1.min(*x2) ..= x1.max(*x2)).contains(&x) && (y1.min(*y2) ..= y1.max(*y2)).contains(&y),
+            points => {
+                // even-odd rule
+                let mut inside = false;
+                let mut prev = points[points.len() - 1];
+                for &point in points {
+                    let ((xi, yi), (xj, yj)) = (point, prev);
+                    if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+                        inside = !inside;
+                    }
+                    prev = point;
+                }
+                inside
+            }
+        }
+    }
+}
+
+impl FromStr for MotionZone {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        const EXPECTED: &str = "zone like detect:0,0.5 1,1 or ignore:0.2,0.1 0.4,0.1 0.3,0.3";
+        let (kind, points) = value.trim().split_once(':').ok_or(EXPECTED)?;
+        let kind = kind.trim().parse().map_err(|()| EXPECTED)?;
+        let points = points
+            .split_whitespace()
+            .map(|point| {
+                let (x, y) = point.split_once(',')?;
+                let coordinate = |value: &str| value.parse::<f64>().ok().filter(|value| (0.0 ..= 1.0).contains(value));
+                Some((coordinate(x)?, coordinate(y)?))
+            })
+            .collect::<Option<Vec<_>>>()
+            .filter(|points| points.len() >= 2)
+            .ok_or(EXPECTED)?;
+        Ok(Self { kind, points })
+    }
+}
+
+impl TryFrom<String> for MotionZone {
+    type Error = &'static str;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> { value.parse() }
+}
+
+impl From<MotionZone> for String {
+    fn from(value: MotionZone) -> Self { value.to_string() }
+}
+
+impl fmt::Display for MotionZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.kind)?;
+        for (i, (x, y)) in self.points.iter().enumerate() {
+            let separator = if i == 0 { "" } else { " " };
+            write!(f, "{separator}{x},{y}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Address the HTTP server listens on, also used as the ngrok forward target.
+///
+/// Written as `host:port`, `[ipv6]:port` or `unix:/path/to.sock`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ListenAddress {
+    /// IPv4 or IPv6 socket address, host names are resolved when the config is loaded.
+    Tcp(SocketAddr),
+    /// Unix domain socket.
+    Unix(PathBuf),
+}
+
+impl ListenAddress {
+    const EXPECTED: &'static str = "host:port, [ipv6]:port or unix:/path/to.sock";
+
+    /// Address which reaches a server listening on `self` from the same host.
+    pub fn local_target(&self) -> Self {
+        match self {
+            ListenAddress::Tcp(addr) if addr.ip().is_unspecified() => {
+                let ip = if addr.is_ipv4() {
+                    Ipv4Addr::LOCALHOST.into()
+                } else {
+                    Ipv6Addr::LOCALHOST.into()
+                };
+                ListenAddress::Tcp(SocketAddr::new(ip, addr.port()))
+            }
+            other => other.clone(),
+        }
+    }
+}
+
+impl FromStr for ListenAddress {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = value.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(Self::EXPECTED);
+            }
+            return Ok(ListenAddress::Unix(path.into()));
+        }
+        value
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .map(ListenAddress::Tcp)
+            .ok_or(Self::EXPECTED)
+    }
+}
+
+impl TryFrom<String> for ListenAddress {
+    type Error = &'static str;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> { value.parse() }
+}
+
+impl From<ListenAddress> for String {
+    fn from(value: ListenAddress) -> Self { value.to_string() }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(addr) => write!(f, "{addr}"),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// How the stream is exposed by `/getvideo`, see [`crate::tunnel`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelKind {
+    /// ngrok tunnel to `ngrok_domain`.
+    #[default]
+    Ngrok,
+    /// No tunnel, the stream is reachable in the local network only.
+    Lan,
+    /// User-supplied command, e.g. an SSH reverse tunnel, exposing `public_url`.
+    Command,
+}
+
+impl FromStr for TunnelKind {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "ngrok" => Ok(TunnelKind::Ngrok),
+            "lan" => Ok(TunnelKind::Lan),
+            "command" => Ok(TunnelKind::Command),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Program configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub tunnel: TunnelKind,
+    /// Shell command started by the `command` tunnel, runs until the tunnel is stopped.
+    pub tunnel_command: String,
+    /// The tunnel is stopped after this time without remote stream viewers, zero disables the check.
+    #[serde(rename = "tunnel_idle_timeout_secs", with = "duration_secs")]
+    pub tunnel_idle_timeout: Duration,
+    /// Hard limit of a tunnel session, zero means unlimited.
+    #[serde(rename = "tunnel_max_session_secs", with = "duration_secs")]
+    pub tunnel_max_session: Duration,
+    pub ngrok_auth_token: String,
+    pub ngrok_domain: String,
+    /// `user:password` required by ngrok before the stream page, empty to disable.
+    pub ngrok_basic_auth: String,
+    /// ngrok OAuth provider like `google` or `github`, empty to disable.
+    pub ngrok_oauth_provider: String,
+    /// Emails (`user@example.com`) or email domains (`@example.com`) allowed by the OAuth provider.
+    pub ngrok_oauth_allow: Vec<String>,
+    /// Address the HTTP server listens on.
+    pub server_address: ListenAddress,
+    /// Base URL the viewers open, `https://{ngrok_domain}` if empty.
+    pub public_url: String,
+    /// Where ngrok forwards the tunnel traffic, derived from `server_address` if not set.
+    pub ngrok_forward_to: Option<ListenAddress>,
+    /// Upper frame rate limit of a single `/stream.mjpg` client.
+    pub mjpeg_max_fps: u32,
+    /// Directory of the files the program writes, e.g. the bandwidth totals.
+    pub data_dir: PathBuf,
+    #[serde(rename = "telegram")]
+    pub telegram_config: TelegramConfig,
+    pub bandwidth: BandwidthConfig,
+    pub access: AccessConfig,
+    pub notify: NotifyConfig,
+    pub recording: RecordingConfig,
+    pub preroll: PrerollConfig,
+    pub motion: MotionConfig,
+    #[serde(rename = "camera")]
+    pub cameras: Vec<CameraConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            cameras: vec![CameraConfig::default()],
+            tunnel: TunnelKind::default(),
+            tunnel_command: String::new(),
+            tunnel_idle_timeout: DEFAULT_TUNNEL_IDLE_TIMEOUT,
+            tunnel_max_session: Duration::ZERO,
+            ngrok_auth_token: String::new(),
+            ngrok_domain: String::new(),
+            ngrok_basic_auth: String::new(),
+            ngrok_oauth_provider: String::new(),
+            ngrok_oauth_allow: Vec::new(),
+            server_address: ListenAddress::Tcp(DEFAULT_SERVER_ADDRESS),
+            public_url: String::new(),
+            ngrok_forward_to: None,
+            mjpeg_max_fps: DEFAULT_MJPEG_MAX_FPS,
+            data_dir: PathBuf::from(DEFAULT_DATA_DIR),
+            telegram_config: TelegramConfig::default(),
+            bandwidth: BandwidthConfig::default(),
+            access: AccessConfig::default(),
+            notify: NotifyConfig::default(),
+            recording: RecordingConfig::default(),
+            preroll: PrerollConfig::default(),
+            motion: MotionConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Builds the configuration from environment variables.
+    ///
+    /// All the problems found are reported at once in the returned [`ConfigError`].
+    pub fn from_env(vars: Vec<(String, String)>) -> Result<Self, ConfigError> { Self::build(Config::default(), vars) }
+
+    /// Loads the TOML config file and overrides its values by the environment variables.
+    ///
+    /// The file is taken from `CONFIG_FILE`, otherwise [`DEFAULT_CONFIG_FILE`] is used if it exists.
+    pub fn load(vars: Vec<(String, String)>) -> Result<Self, ConfigError> {
+        let path = vars
+            .iter()
+            .find(|(key, _)| key == "CONFIG_FILE")
+            .map(|(_, path)| PathBuf::from(path))
+            .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()));
+        let Some(path) = path else {
+            return Self::from_env(vars);
+        };
+        let file_config = Self::from_file(&path).map_err(|issue| ConfigError { issues: vec![issue] })?;
+        Self::build(file_config, vars)
+    }
+
+    /// Reads a TOML config file, missing values are set to defaults.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigIssue> {
+        let file_error = |error: String| ConfigIssue::File {
+            path: path.to_path_buf(),
+            error,
+        };
+        let content = std::fs::read_to_string(path).map_err(|e| file_error(e.to_string()))?;
+        toml::from_str(&content).map_err(|e| file_error(e.to_string()))
+    }
+
+    /// Serializes the configuration to TOML, e.g. to dump the effective settings.
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> { toml::to_string(self) }
+
+    /// Same as [`Config::to_toml`] with the secrets replaced by `<hidden>`, safe to print to logs.
+    pub fn to_toml_redacted(&self) -> Result<String, toml::ser::Error> {
+        fn hide(secret: &mut String) {
+            if !secret.is_empty() {
+                *secret = "<hidden>".to_owned();
+            }
+        }
+        let mut config = self.clone();
+        hide(&mut config.telegram_config.token);
+        hide(&mut config.ngrok_auth_token);
+        hide(&mut config.ngrok_basic_auth);
+        hide(&mut config.access.token_secret);
+        config.to_toml()
+    }
+
+    fn build(mut config: Config, vars: Vec<(String, String)>) -> Result<Self, ConfigError> {
+        let mut env = EnvReader::new(vars);
+        config.apply_env(&mut env);
+        config.validate(&mut env.issues);
+        env.finish(config)
+    }
+
+    /// Overrides settings by the environment variables which are set.
+    fn apply_env(&mut self, env: &mut EnvReader) {
+        // CAMERAS=front,back enables several cameras, each configured by CAMERA_<NAME>_* variables
+        if let Some(names) = env.list::<String>("CAMERAS", "comma-separated list of camera names") {
+            self.cameras = names
+                .iter()
+                .map(|name| self.camera(name).cloned().unwrap_or_else(|| CameraConfig::new(name)))
+                .collect();
+        }
+        for camera in &mut self.cameras {
+            let prefix = camera.name.to_uppercase().replace('-', "_");
+            camera.apply_env(env, &prefix);
+        }
+
+        env.set("TUNNEL", "one of ngrok, lan, command", &mut self.tunnel);
+        env.set_string("TUNNEL_COMMAND", &mut self.tunnel_command);
+        if let Some(timeout) = env.parse::<u64>("TUNNEL_IDLE_TIMEOUT_SECS", "timeout in seconds, 0 disables") {
+            self.tunnel_idle_timeout = Duration::from_secs(timeout);
+        }
+        if let Some(limit) = env.parse::<u64>("TUNNEL_MAX_SESSION_SECS", "session length in seconds, 0 disables") {
+            self.tunnel_max_session = Duration::from_secs(limit);
+        }
+        env.set_string("NGROK_AUTH_TOKEN", &mut self.ngrok_auth_token);
+        env.set_string("NGROK_DOMAIN", &mut self.ngrok_domain);
+        env.set_string("NGROK_BASIC_AUTH", &mut self.ngrok_basic_auth);
+        env.set_string("NGROK_OAUTH_PROVIDER", &mut self.ngrok_oauth_provider);
+        if let Some(allow) = env.list("NGROK_OAUTH_ALLOW", "comma-separated list of emails and @domains") {
+            self.ngrok_oauth_allow = allow;
+        }
+        env.set("SERVER_ADDRESS", ListenAddress::EXPECTED, &mut self.server_address);
+        env.set_string("PUBLIC_URL", &mut self.public_url);
+        if let Some(forward_to) = env.parse("NGROK_FORWARD_TO", ListenAddress::EXPECTED) {
+            self.ngrok_forward_to = Some(forward_to);
+        }
+        env.set(
+            "MJPEG_MAX_FPS",
+            "positive number of frames per second",
+            &mut self.mjpeg_max_fps,
+        );
+
+        if let Some(data_dir) = env.get("DATA_DIR") {
+            self.data_dir = data_dir.into();
+        }
+
+        let access = &mut self.access;
+        env.set("STREAM_REQUIRE_TOKEN", "true or false", &mut access.require_token);
+        if let Some(ttl) = env.parse::<u64>("STREAM_TOKEN_TTL_SECS", "token lifetime in seconds") {
+            access.token_ttl = Duration::from_secs(ttl);
+        }
+        env.set_string("STREAM_TOKEN_SECRET", &mut access.token_secret);
+
+        let bandwidth = &mut self.bandwidth;
+        env.set(
+            "BANDWIDTH_BUDGET_MB",
+            "budget in megabytes, 0 disables",
+            &mut bandwidth.budget_mb,
+        );
+        if let Some(levels) = env.list("BANDWIDTH_WARN_PERCENT", "comma-separated list of percents like 50,80,95") {
+            bandwidth.warn_percent = levels;
+        }
+        env.set("BANDWIDTH_EXHAUSTED", "one of refuse, low_rate", &mut bandwidth.exhausted);
+        env.set(
+            "BANDWIDTH_LOW_RATE_FPS",
+            "positive number of frames per second",
+            &mut bandwidth.low_rate_fps,
+        );
+
+        let notify = &mut self.notify;
+        if let Some(events) = env.list(
+            "NOTIFY_EVENTS",
+            "comma-separated list of command, unauthorized, invalid_command, tunnel, error",
+        ) {
+            notify.events = events;
+        }
+        env.set("NOTIFY_OWN_ACTIONS", "true or false", &mut notify.own_actions);
+        if let Some(quiet_hours) = env.parse_with("NOTIFY_QUIET_HOURS", QuietHours::EXPECTED, |value| {
+            if value.is_empty() || value == "off" {
+                Some(None)
+            } else {
+                value.parse().ok().map(Some)
+            }
+        }) {
+            notify.quiet_hours = quiet_hours;
+        }
+        env.set(
+            "NOTIFY_UTC_OFFSET_MINUTES",
+            "minutes east of UTC like 120",
+            &mut notify.utc_offset_minutes,
+        );
+        if let Some(window) = env.parse::<u64>("NOTIFY_DIGEST_WINDOW_SECS", "window in seconds, 0 disables") {
+            notify.digest_window = Duration::from_secs(window);
+        }
+
+        let recording = &mut self.recording;
+        env.set("RECORDING_ENABLED", "true or false", &mut recording.enabled);
+        env.set("RECORDING_MODE", "one of continuous, events", &mut recording.mode);
+        if let Some(length) = env.parse::<u64>("RECORDING_POST_EVENT_SECS", "length in seconds") {
+            recording.post_event = Duration::from_secs(length);
+        }
+        if let Some(dir) = env.get("RECORDING_DIR") {
+            recording.dir = Some(dir.into());
+        }
+        if let Some(length) = env.parse::<u64>("RECORDING_SEGMENT_SECS", "segment length in seconds") {
+            recording.segment_length = Duration::from_secs(length);
+        }
+        env.set(
+            "RECORDING_MAX_FPS",
+            "number of frames per second, 0 keeps every frame",
+            &mut recording.max_fps,
+        );
+        env.set(
+            "RECORDING_MAX_AGE_HOURS",
+            "age in hours, 0 disables",
+            &mut recording.max_age_hours,
+        );
+        env.set(
+            "RECORDING_MAX_SIZE_MB",
+            "size in megabytes, 0 disables",
+            &mut recording.max_size_mb,
+        );
+
+        if let Some(length) = env.parse::<u64>("PREROLL_SECS", "length in seconds, 0 disables") {
+            self.preroll.length = Duration::from_secs(length);
+        }
+        env.set("PREROLL_MAX_MB", "positive size in megabytes", &mut self.preroll.max_mb);
+
+        let motion = &mut self.motion;
+        env.set("MOTION_ENABLED", "true or false", &mut motion.enabled);
+        env.set("MOTION_ARMED", "true or false", &mut motion.armed);
+        env.set("MOTION_SENSITIVITY", "number from 1 to 100", &mut motion.sensitivity);
+        env.set(
+            "MOTION_MIN_AREA_PERCENT",
+            "percent of the picture like 2.5",
+            &mut motion.min_area_percent,
+        );
+        if let Some(cooldown) = env.parse::<u64>("MOTION_COOLDOWN_SECS", "cooldown in seconds") {
+            motion.cooldown = Duration::from_secs(cooldown);
+        }
+        env.set("MOTION_MAX_FPS", "positive number of frames per second", &mut motion.max_fps);
+        if let Some(length) = env.parse::<u64>("MOTION_CLIP_SECS", "length in seconds, 0 disables") {
+            motion.clip_length = Duration::from_secs(length);
+        }
+
+        let telegram = &mut self.telegram_config;
+        env.set_string("TELEGRAM_TOKEN", &mut telegram.token);
+        env.set(
+            "TELEGRAM_ADMIN_USER_ID",
+            "numeric Telegram user id",
+            &mut telegram.admin_user_id,
+        );
+        if let Some(ids) = env.list(
+            "TELEGRAM_ALLOWED_USER_IDS",
+            "comma-separated list of numeric Telegram user ids",
+        ) {
+            telegram.allowed_user_ids = ids;
+        }
+        if let Some(ids) = env.list(
+            "TELEGRAM_OPERATOR_USER_IDS",
+            "comma-separated list of numeric Telegram user ids",
+        ) {
+            telegram.operator_user_ids = ids;
+        }
+        if let Some(timeout) = env.parse::<u64>("PHOTO_TIMEOUT_MS", "timeout in milliseconds") {
+            telegram.photo_timeout = Duration::from_millis(timeout);
+        }
+        env.set("PHOTO_SKIP_FRAMES", "number of frames", &mut telegram.photo_skip_frames);
+        env.set(
+            "BURST_MAX_FRAMES",
+            "number of frames from 1 to 10",
+            &mut telegram.burst_max_frames,
+        );
+        if let Some(interval) = env.parse::<u64>("BURST_MIN_INTERVAL_MS", "interval in milliseconds") {
+            telegram.burst_min_interval = Duration::from_millis(interval);
+        }
+        if let Some(length) = env.parse::<u64>("CLIP_MAX_SECS", "length in seconds") {
+            telegram.clip_max_length = Duration::from_secs(length);
+        }
+        env.set("CLIP_MAX_MB", "size in megabytes from 1 to 50", &mut telegram.clip_max_mb);
+    }
+
+    /// Checks the values which must be set and the relations between settings.
+    fn validate(&mut self, issues: &mut Vec<ConfigIssue>) {
+        let required = [
+            (
+                "TELEGRAM_TOKEN (telegram.token)",
+                self.telegram_config.token.is_empty(),
+                "bot token from BotFather",
+            ),
+            (
+                "TELEGRAM_ADMIN_USER_ID (telegram.admin_user_id)",
+                self.telegram_config.admin_user_id == 0,
+                "numeric Telegram user id",
+            ),
+            (
+                "NGROK_AUTH_TOKEN (ngrok_auth_token)",
+                self.tunnel == TunnelKind::Ngrok && self.ngrok_auth_token.is_empty(),
+                "ngrok auth token",
+            ),
+            (
+                "NGROK_DOMAIN (ngrok_domain)",
+                self.tunnel == TunnelKind::Ngrok && self.ngrok_domain.is_empty(),
+                "ngrok static domain",
+            ),
+            (
+                "TUNNEL_COMMAND (tunnel_command)",
+                self.tunnel == TunnelKind::Command && self.tunnel_command.is_empty(),
+                "shell command which opens the tunnel",
+            ),
+            (
+                "PUBLIC_URL (public_url)",
+                self.public_url.is_empty()
+                    && (self.tunnel == TunnelKind::Command
+                        || self.tunnel == TunnelKind::Lan && matches!(self.server_address, ListenAddress::Unix(_))),
+                "URL the tunnel exposes the stream at",
+            ),
+        ];
+        for (key, missing, expected) in required {
+            if missing {
+                issues.push(ConfigIssue::Missing {
+                    key: key.to_string(),
+                    expected,
+                });
+            }
+        }
+
+        if self.cameras.is_empty() {
+            issues.push(ConfigIssue::Missing {
+                key: "CAMERAS".to_string(),
+                expected: "at least one camera name",
+            });
+        }
+        for (i, camera) in self.cameras.iter().enumerate() {
+            if !is_valid_camera_name(&camera.name) {
+                issues.push(ConfigIssue::invalid(
+                    "CAMERAS",
+                    &camera.name,
+                    "camera names of letters, digits, - and _",
+                ));
+            } else if self.cameras[.. i].iter().any(|other| other.name == camera.name) {
+                issues.push(ConfigIssue::invalid("CAMERAS", &camera.name, "unique camera names"));
+            }
+            camera.validate(issues);
+        }
+        if !self.public_url.is_empty() && !is_valid_public_url(&self.public_url) {
+            issues.push(ConfigIssue::invalid(
+                "PUBLIC_URL (public_url)",
+                &self.public_url,
+                "http(s) URL like https://camera.example.com",
+            ));
+        }
+        if self.mjpeg_max_fps == 0 {
+            issues.push(ConfigIssue::invalid(
+                "MJPEG_MAX_FPS",
+                "0",
+                "positive number of frames per second",
+            ));
+        }
+
+        if let Some(level) = self.bandwidth.warn_percent.iter().find(|&&level| level == 0 || level > 100) {
+            issues.push(ConfigIssue::invalid(
+                "BANDWIDTH_WARN_PERCENT (bandwidth.warn_percent)",
+                &level.to_string(),
+                "percents from 1 to 100",
+            ));
+        }
+        if !self.ngrok_basic_auth.is_empty() {
+            // ngrok requires 8 to 128 characters long passwords
+            let valid = self
+                .ngrok_basic_auth
+                .split_once(':')
+                .is_some_and(|(user, password)| !user.is_empty() && (8 ..= 128).contains(&password.len()));
+            if !valid {
+                issues.push(ConfigIssue::invalid(
+                    "NGROK_BASIC_AUTH (ngrok_basic_auth)",
+                    "<hidden>",
+                    "user:password with a password of 8 to 128 characters",
+                ));
+            }
+        }
+        if self.access.require_token && self.access.token_ttl.is_zero() {
+            issues.push(ConfigIssue::invalid(
+                "STREAM_TOKEN_TTL_SECS (access.token_ttl_secs)",
+                "0",
+                "positive token lifetime in seconds",
+            ));
+        }
+        if self.bandwidth.low_rate_fps == 0 {
+            issues.push(ConfigIssue::invalid(
+                "BANDWIDTH_LOW_RATE_FPS (bandwidth.low_rate_fps)",
+                "0",
+                "positive number of frames per second",
+            ));
+        }
+
+        if self.notify.utc_offset_minutes.abs() > MAX_UTC_OFFSET_MINUTES {
+            issues.push(ConfigIssue::invalid(
+                "NOTIFY_UTC_OFFSET_MINUTES (notify.utc_offset_minutes)",
+                &self.notify.utc_offset_minutes.to_string(),
+                "offset from -840 to 840 minutes",
+            ));
+        }
+
+        if !(1 ..= MAX_BURST_FRAMES).contains(&self.telegram_config.burst_max_frames) {
+            issues.push(ConfigIssue::invalid(
+                "BURST_MAX_FRAMES (telegram.burst_max_frames)",
+                &self.telegram_config.burst_max_frames.to_string(),
+                "number of frames from 1 to 10",
+            ));
+        }
+
+        if self.telegram_config.burst_min_interval > MAX_BURST_INTERVAL {
+            issues.push(ConfigIssue::invalid(
+                "BURST_MIN_INTERVAL_MS (telegram.burst_min_interval_ms)",
+                &self.telegram_config.burst_min_interval.as_millis().to_string(),
+                "interval from 0 to 60000 milliseconds",
+            ));
+        }
+
+        if self.telegram_config.clip_max_length.is_zero() {
+            issues.push(ConfigIssue::invalid(
+                "CLIP_MAX_SECS (telegram.clip_max_secs)",
+                "0",
+                "positive length in seconds",
+            ));
+        }
+
+        if !(1 ..= MAX_CLIP_MB).contains(&self.telegram_config.clip_max_mb) {
+            issues.push(ConfigIssue::invalid(
+                "CLIP_MAX_MB (telegram.clip_max_mb)",
+                &self.telegram_config.clip_max_mb.to_string(),
+                "size in megabytes from 1 to 50",
+            ));
+        }
+
+        if self.recording.segment_length.is_zero() {
+            issues.push(ConfigIssue::invalid(
+                "RECORDING_SEGMENT_SECS (recording.segment_secs)",
+                "0",
+                "positive segment length in seconds",
+            ));
+        }
+
+        if !self.preroll.length.is_zero() && self.preroll.max_mb == 0 {
+            issues.push(ConfigIssue::invalid(
+                "PREROLL_MAX_MB (preroll.max_mb)",
+                "0",
+                "positive size in megabytes",
+            ));
+        }
+
+        if !(1 ..= 100).contains(&self.motion.sensitivity) {
+            issues.push(ConfigIssue::invalid(
+                "MOTION_SENSITIVITY (motion.sensitivity)",
+                &self.motion.sensitivity.to_string(),
+                "number from 1 to 100",
+            ));
+        }
+        if !(self.motion.min_area_percent > 0.0 && self.motion.min_area_percent <= 100.0) {
+            issues.push(ConfigIssue::invalid(
+                "MOTION_MIN_AREA_PERCENT (motion.min_area_percent)",
+                &self.motion.min_area_percent.to_string(),
+                "percent of the picture above 0 and up to 100",
+            ));
+        }
+        if self.motion.max_fps == 0 {
+            issues.push(ConfigIssue::invalid(
+                "MOTION_MAX_FPS (motion.max_fps)",
+                "0",
+                "positive number of frames per second",
+            ));
+        }
+        // the motion clips are sent like `/clip`, with the same limits
+        if self.motion.clip_length > self.telegram_config.clip_max_length {
+            issues.push(ConfigIssue::invalid(
+                "MOTION_CLIP_SECS (motion.clip_secs)",
+                &self.motion.clip_length.as_secs().to_string(),
+                "length in seconds up to CLIP_MAX_SECS (telegram.clip_max_secs)",
+            ));
+        }
+
+        let telegram = &mut self.telegram_config;
+        if telegram.admin_user_id != 0 && !telegram.allowed_user_ids.contains(&telegram.admin_user_id) {
+            telegram.allowed_user_ids.push(telegram.admin_user_id);
+        }
+    }
+
+    /// Directory of the recorded segments.
+    pub fn recording_dir(&self) -> PathBuf {
+        self.recording
+            .dir
+            .clone()
+            .unwrap_or_else(|| self.data_dir.join(DEFAULT_RECORDING_DIR))
+    }
+
+    /// Looks up a camera by name, an empty name selects the first (default) camera.
+    pub fn camera(&self, name: &str) -> Option<&CameraConfig> {
+        if name.is_empty() {
+            self.cameras.first()
+        } else {
+            self.cameras.iter().find(|camera| camera.name == name)
+        }
+    }
+
+    /// Base URL the viewers open, without a trailing slash.
+    ///
+    /// Defaults to the ngrok domain, or to the LAN address of the server in the `lan` tunnel mode.
+    pub fn public_url(&self) -> String {
+        if !self.public_url.is_empty() {
+            return self.public_url.trim_end_matches('/').to_string();
+        }
+        match (self.tunnel, &self.server_address) {
+            (TunnelKind::Lan, ListenAddress::Tcp(addr)) => {
+                let ip = if addr.ip().is_unspecified() {
+                    local_ip(addr.is_ipv6()).unwrap_or(addr.ip())
+                } else {
+                    addr.ip()
+                };
+                format!("http://{}", SocketAddr::new(ip, addr.port()))
+            }
+            _ => format!("https://{}", self.ngrok_domain),
+        }
+    }
+
+    /// Address ngrok forwards to, the local side of [`Config::server_address`] by default.
+    pub fn ngrok_forward_to(&self) -> ListenAddress {
+        self.ngrok_forward_to
+            .clone()
+            .unwrap_or_else(|| self.server_address.local_target())
+    }
+
+    pub fn camera_names(&self) -> Vec<String> { self.cameras.iter().map(|camera| camera.name.clone()).collect() }
+}
+
+impl CameraConfig {
+    /// Applies `CAMERA_<KEY>` variables, `CAMERA_<PREFIX>_<KEY>` take precedence.
+    fn apply_env(&mut self, env: &mut EnvReader, prefix: &str) {
+        let key = |env: &EnvReader, name: &str| {
+            Some(format!("CAMERA_{prefix}_{name}"))
+                .filter(|key| env.get(key).is_some())
+                .unwrap_or_else(|| format!("CAMERA_{name}"))
+        };
+
+        let source_key = key(env, "SOURCE");
+        let replay_key = key(env, "REPLAY_PATH");
+        if let Some(source) = env.get(&source_key) {
+            match source.as_str() {
+                "v4l2" => self.source = CameraSource::V4l2,
+                "test" => self.source = CameraSource::TestPattern,
+                "replay" => match env.get(&replay_key) {
+                    Some(path) => self.source = CameraSource::Replay(path.into()),
+                    // the path may come from the config file
+                    None if matches!(self.source, CameraSource::Replay(_)) => {}
+                    None => env.issues.push(ConfigIssue::Missing {
+                        key: replay_key,
+                        expected: "path to a JPEG directory, a JPEG or an MJPEG file",
+                    }),
+                },
+                _ => env.invalid(&source_key, "one of v4l2, test, replay"),
+            }
+        }
+
+        let width_key = key(env, "WIDTH");
+        env.set(&width_key, "width in pixels", &mut self.width);
+        let height_key = key(env, "HEIGHT");
+        env.set(&height_key, "height in pixels", &mut self.height);
+        let dev_idx_key = key(env, "DEV_IDX");
+        env.set(&dev_idx_key, "V4L2 device index 0-255", &mut self.dev_idx);
+        let interval_key = key(env, "INTERVAL");
+        if let Some(interval) = env.parse_with(&interval_key, "frame interval like 1/30", parse_interval) {
+            self.interval = interval;
+        }
+        let fourcc_key = key(env, "FOURCC");
+        if let Some(fourcc) = env.parse_with(&fourcc_key, "four character code like MJPG", parse_fourcc) {
+            self.fourcc = fourcc;
+        }
+        let buf_count_key = key(env, "BUF_COUNT");
+        env.set(&buf_count_key, "number of capture buffers", &mut self.buf_size);
+        let zones_key = key(env, "ZONES");
+        if let Some(zones) = env.parse_with(&zones_key, MotionZone::EXPECTED, |value| {
+            value
+                .split(';')
+                .filter(|zone| !zone.trim().is_empty())
+                .map(|zone| zone.parse().ok())
+                .collect()
+        }) {
+            self.zones = zones;
+        }
+    }
+
+    fn validate(&self, issues: &mut Vec<ConfigIssue>) {
+        let key = |name: &str| format!("camera {}: {}", self.name, name);
+        if self.width == 0 || self.height == 0 {
+            issues.push(ConfigIssue::Invalid {
+                key: key("width/height"),
+                value: format!("{}x{}", self.width, self.height),
+                expected: "non-zero resolution",
+            });
+        }
+        if self.interval.0 == 0 || self.interval.1 == 0 {
+            issues.push(ConfigIssue::Invalid {
+                key: key("interval"),
+                value: format!("{}/{}", self.interval.0, self.interval.1),
+                expected: "frame interval like 1/30",
+            });
+        }
+        if self.buf_size == 0 {
+            issues.push(ConfigIssue::Invalid {
+                key: key("buf_size"),
+                value: "0".to_string(),
+                expected: "number of capture buffers",
+            });
+        }
+    }
+}
+
+/// Parses a frame interval written as `1/30`.
+fn parse_interval(value: &str) -> Option<(u32, u32)> {
+    let (num, den) = value.split_once('/')?;
+    Some((num.trim().parse().ok()?, den.trim().parse().ok()?))
+}
+
+/// Parses a V4L2 four character code such as `MJPG`.
+pub fn parse_fourcc(value: &str) -> Option<[u8; 4]> { value.is_ascii().then(|| value.as_bytes().try_into().ok()).flatten() }
+
+/// (De)serializes a fourcc as a string instead of a byte array.
+mod fourcc {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(fourcc: &[u8; 4], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from_utf8_lossy(fourcc))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 4], D::Error> {
+        let value = String::deserialize(deserializer)?;
+        super::parse_fourcc(&value).ok_or_else(|| de::Error::custom("expected a four character code like MJPG"))
+    }
+}
+
+/// (De)serializes a [`Duration`] as milliseconds.
+mod duration_ms {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(u64::try_from(duration.as_millis()).unwrap_or(u64::MAX))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+/// Address of the interface used for outgoing traffic, UDP connect does not send any packets.
+fn local_ip(ipv6: bool) -> Option<IpAddr> {
+    let (bind, remote) = if ipv6 {
+        ("[::]:0", "[2001:4860:4860::8888]:80")
+    } else {
+        ("0.0.0.0:0", "8.8.8.8:80")
+    };
+    let socket = UdpSocket::bind(bind).ok()?;
+    socket.connect(remote).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+fn is_valid_public_url(value: &str) -> bool {
+    reqwest::Url::parse(value).is_ok_and(|url| {
+        matches!(url.scheme(), "http" | "https") && url.has_host() && url.query().is_none() && url.fragment().is_none()
+    })
+}
+
+/// (De)serializes a [`Duration`] as whole seconds.
+mod duration_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
+
+fn is_valid_camera_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// A single problem found in the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigIssue {
+    Missing {
+        key: String,
+        expected: &'static str,
+    },
+    Invalid {
+        key: String,
+        value: String,
+        expected: &'static str,
+    },
+    /// The config file can not be read or parsed.
+    File {
+        path: PathBuf,
+        error: String,
+    },
+}
+
+impl ConfigIssue {
+    fn invalid(key: &str, value: &str, expected: &'static str) -> Self {
+        ConfigIssue::Invalid {
+            key: key.to_string(),
+            value: value.to_string(),
+            expected,
+        }
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigIssue::Missing { key, expected } => write!(f, "{key} is not set, expected {expected}"),
+            ConfigIssue::Invalid { key, value, expected } => {
+                write!(f, "{key} has invalid value {value:?}, expected {expected}")
+            }
+            ConfigIssue::File { path, error } => write!(f, "config file {}: {}", path.display(), error),
+        }
+    }
+}
+
+/// Every problem found while loading the configuration.
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    pub issues: Vec<ConfigIssue>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration, {} problem(s) found:", self.issues.len())?;
+        for issue in &self.issues {
+            write!(f, "\n  - {issue}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Reads environment variables and collects the problems instead of failing on the first one.
+struct EnvReader {
+    vars: HashMap<String, String>,
+    issues: Vec<ConfigIssue>,
+}
+
+impl EnvReader {
+    fn new(vars: Vec<(String, String)>) -> Self {
+        Self {
+            vars: vars.into_iter().collect(),
+            issues: Vec::new(),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<String> { self.vars.get(key).map(|value| value.trim().to_string()) }
+
+    fn invalid(&mut self, key: &str, expected: &'static str) {
+        let value = self.get(key).unwrap_or_default();
+        self.issues.push(ConfigIssue::invalid(key, &value, expected));
+    }
+
+    /// Parses the variable if it is set, records an issue if it can not be parsed.
+    fn parse<T: FromStr>(&mut self, key: &str, expected: &'static str) -> Option<T> {
+        let value = self.get(key)?;
+        let parsed = value.parse().ok();
+        if parsed.is_none() {
+            self.invalid(key, expected);
+        }
+        parsed
+    }
+
+    /// Like [`EnvReader::parse`] with a custom parser.
+    fn parse_with<T>(&mut self, key: &str, expected: &'static str, parser: impl Fn(&str) -> Option<T>) -> Option<T> {
+        let value = self.get(key)?;
+        let parsed = parser(&value);
+        if parsed.is_none() {
+            self.invalid(key, expected);
+        }
+        parsed
+    }
+
+    fn set<T: FromStr>(&mut self, key: &str, expected: &'static str, target: &mut T) {
+        if let Some(value) = self.parse(key, expected) {
+            *target = value;
+        }
+    }
+
+    fn set_string(&mut self, key: &str, target: &mut String) {
+        if let Some(value) = self.get(key) {
+            *target = value;
+        }
+    }
+
+    /// Parses a comma-separated list, empty items are ignored.
+    fn list<T: FromStr>(&mut self, key: &str, expected: &'static str) -> Option<Vec<T>> {
+        let value = self.get(key)?;
+        let items: Result<Vec<T>, _> = value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::parse)
+            .collect();
+        match items {
+            Ok(items) => Some(items),
+            Err(_) => {
+                self.invalid(key, expected);
+                None
+            }
+        }
+    }
+
+    fn finish<T>(self, value: T) -> Result<T, ConfigError> {
+        if self.issues.is_empty() {
+            return Ok(value);
+        }
+        // shared camera settings are checked once per camera
+        let mut issues: Vec<ConfigIssue> = Vec::with_capacity(self.issues.len());
+        for issue in self.issues {
+            if !issues.contains(&issue) {
+                issues.push(issue);
+            }
+        }
+        Err(ConfigError { issues })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone(value: &str) -> MotionZone { value.parse().unwrap() }
+
+    #[test]
+    fn rectangle_zone() {
+        let rectangle = zone("detect:0.6,0.6 0.2,0.2");
+        assert!(rectangle.contains(0.4, 0.4));
+        assert!(!rectangle.contains(0.1, 0.4));
+        // both corners and all edges are inside
+        assert!(rectangle.contains(0.2, 0.2));
+        assert!(rectangle.contains(0.6, 0.4));
+        assert!(rectangle.contains(0.4, 0.6));
+    }
+
+    #[test]
+    fn concave_polygon_zone() {
+        // a V notch from the top edge down to the middle
+        let polygon = zone("detect:0,0 0.5,0.5 1,0 1,1 0,1");
+        assert!(polygon.contains(0.1, 0.2));
+        assert!(polygon.contains(0.9, 0.2));
+        assert!(polygon.contains(0.5, 0.8));
+        assert!(!polygon.contains(0.5, 0.2));
+    }
+
+    #[test]
+    fn point_on_polygon_edge() {
+        let square = zone("ignore:0.2,0.2 0.6,0.2 0.6,0.6 0.2,0.6");
+        // the even-odd rule counts the left and top edges in, the right and bottom ones out,
+        // so neighbouring zones do not overlap
+        assert!(square.contains(0.2, 0.4));
+        assert!(square.contains(0.4, 0.2));
+        assert!(!square.contains(0.6, 0.4));
+        assert!(!square.contains(0.4, 0.6));
+    }
+}
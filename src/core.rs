@@ -1,107 +1,351 @@
-use roboplc::locking::RwLock;
+use crate::access::StreamTokens;
+use crate::bandwidth::BandwidthMeter;
+use crate::config::{Config, TunnelKind};
+use crate::motion::MotionState;
+use crate::notify::Notifier;
+use crate::preroll::PrerollBuffer;
+use crate::recording::SegmentIndex;
+use crate::status::PipelineStats;
+use crate::tunnel::{tunnel_provider, TunnelProvider};
+use crate::users::UserStore;
 use roboplc::{DataDeliveryPolicy, DeliveryPolicy};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize};
 use std::sync::Arc;
-use tokio::sync::{oneshot, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, watch};
 
-const BUF_COUNT: u32 = 20;
-const DEFAULT_CAMERA_WIDTH: u32 = 640;
-const DEFAULT_CAMERA_HEIGHT: u32 = 480;
-const DEFAULT_CAMERA_DEV_IDX: u8 = 0;
-const DEFAULT_CAMERA_INTERVAL: (u32, u32) = (1, 30);
-const DEFAULT_CAMERA_FOURCC: [u8; 4] = *b"MJPG";
+
+/// JPEG frame tagged with the name of the camera it was captured from.
+#[derive(Clone, Debug)]
+pub struct CameraFrame {
+    pub camera: String,
+    pub data: Vec<u8>,
+}
+
+/// Something worth recording happened on a camera.
+#[derive(Clone, Debug)]
+pub struct CameraEvent {
+    pub camera: String,
+    pub kind: EventKind,
+    /// Frame the event was detected on, if any.
+    pub frame: Option<Arc<Vec<u8>>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventKind {
+    /// A user recorded a clip.
+    Clip,
+    /// [`crate::workers::MotionDetector`] found this share of the picture in percent changed.
+    Motion { area_percent: f64 },
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventKind::Clip => write!(f, "clip"),
+            EventKind::Motion { .. } => write!(f, "motion"),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub enum WorkerMessage {
-    Frame(Vec<u8>),
+    Frame(CameraFrame),
+    Event(CameraEvent),
     Terminate,
 }
 
 impl DataDeliveryPolicy for WorkerMessage {
     fn delivery_policy(&self) -> DeliveryPolicy { DeliveryPolicy::Latest }
+
+    /// Frames of different cameras must not replace each other in the queues.
+    fn eq_kind(&self, other: &Self) -> bool {
+        match (self, other) {
+            (WorkerMessage::Frame(a), WorkerMessage::Frame(b)) => a.camera == b.camera,
+            (WorkerMessage::Event(a), WorkerMessage::Event(b)) => a.camera == b.camera,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl WorkerMessage {
+    /// Returns true if the message is a frame of the given camera.
+    pub fn is_frame_of(&self, camera: &str) -> bool { matches!(self, WorkerMessage::Frame(frame) if frame.camera == camera) }
+
+    /// Returns true if the message is an event of the given camera.
+    pub fn is_event_of(&self, camera: &str) -> bool { matches!(self, WorkerMessage::Event(event) if event.camera == camera) }
+}
+
+/// Broadcasts camera frames to any number of stream viewers.
+///
+/// Every viewer subscribes its own bounded receiver, a viewer which falls behind loses
+/// the oldest frames instead of holding back the others.
+#[derive(Debug)]
+pub struct FrameFanout {
+    channels: HashMap<String, broadcast::Sender<Arc<Vec<u8>>>>,
+}
+
+impl FrameFanout {
+    pub fn new(cameras: &[String], queue_size: usize) -> Self {
+        let channels = cameras
+            .iter()
+            .map(|camera| (camera.clone(), broadcast::channel(queue_size).0))
+            .collect();
+        Self { channels }
+    }
+
+    /// Sends a frame to all current viewers of the camera, returns the number of viewers.
+    pub fn publish(&self, camera: &str, frame: Arc<Vec<u8>>) -> usize {
+        self.channels
+            .get(camera)
+            .and_then(|tx| tx.send(frame).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn subscribe(&self, camera: &str) -> Option<broadcast::Receiver<Arc<Vec<u8>>>> {
+        self.channels.get(camera).map(broadcast::Sender::subscribe)
+    }
+}
+
+/// A frame kept in [`FrameCache`] together with its capture time.
+#[derive(Clone, Debug)]
+pub struct CapturedFrame {
+    pub data: Arc<Vec<u8>>,
+    pub captured_at: SystemTime,
+    /// Sequence number of the frame, increases by one with every captured frame.
+    pub seq: u64,
 }
+
+/// Latest captured frame of every camera, updated by [`crate::workers::DetectorVideo`].
+#[derive(Debug, Default)]
+pub struct FrameCache {
+    cameras: HashMap<String, watch::Sender<Option<CapturedFrame>>>,
+}
+
+impl FrameCache {
+    pub fn new(cameras: &[String]) -> Self {
+        let cameras = cameras
+            .iter()
+            .map(|camera| (camera.clone(), watch::channel(None).0))
+            .collect();
+        Self { cameras }
+    }
+
+    /// Stores a new frame of the camera.
+    pub fn update(&self, camera: &str, data: Arc<Vec<u8>>) {
+        if let Some(tx) = self.cameras.get(camera) {
+            tx.send_modify(|latest| {
+                let seq = latest.as_ref().map_or(0, |frame| frame.seq + 1);
+                *latest = Some(CapturedFrame {
+                    data,
+                    captured_at: SystemTime::now(),
+                    seq,
+                });
+            });
+        }
+    }
+
+    /// Returns the latest frame of the camera, `None` if nothing has been captured yet.
+    pub fn latest(&self, camera: &str) -> Option<CapturedFrame> { self.cameras.get(camera).and_then(|tx| tx.borrow().clone()) }
+
+    /// Waits for a frame captured after the call, skipping `skip` frames first (e.g. to let
+    /// auto-exposure settle).
+    pub async fn next_frame(&self, camera: &str, skip: usize, timeout: Duration) -> Result<CapturedFrame, FrameError> {
+        let mut rx = self
+            .cameras
+            .get(camera)
+            .ok_or_else(|| FrameError::UnknownCamera(camera.to_string()))?
+            .subscribe();
+        // the watch channel merges updates, so the frames are counted by their sequence numbers
+        let first_seq = rx.borrow_and_update().as_ref().map_or(0, |frame| frame.seq + 1);
+        let wanted_seq = first_seq + skip as u64;
+
+        let mut received = 0;
+        let wait = async {
+            loop {
+                if rx.changed().await.is_err() {
+                    return Err(FrameError::Closed(camera.to_string()));
+                }
+                if let Some(frame) = rx.borrow_and_update().clone() {
+                    received = usize::try_from(frame.seq + 1 - first_seq).unwrap_or(usize::MAX);
+                    if frame.seq >= wanted_seq {
+                        return Ok(frame);
+                    }
+                }
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.unwrap_or_else(|_| {
+            Err(FrameError::Timeout {
+                camera: camera.to_string(),
+                timeout,
+                received,
+            })
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    UnknownCamera(String),
+    /// No (or not enough) frames arrived in time.
+    Timeout {
+        camera: String,
+        timeout: Duration,
+        received: usize,
+    },
+    Closed(String),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::UnknownCamera(camera) => write!(f, "unknown camera {camera}"),
+            FrameError::Timeout {
+                camera,
+                timeout,
+                received: 0,
+            } => write!(
+                f,
+                "camera {camera} sent no frames within {:.1} s, it may be disconnected or busy",
+                timeout.as_secs_f64()
+            ),
+            FrameError::Timeout {
+                camera,
+                timeout,
+                received,
+            } => write!(
+                f,
+                "camera {camera} sent only {received} frame(s) within {:.1} s, not enough to skip the warm-up frames",
+                timeout.as_secs_f64()
+            ),
+            FrameError::Closed(camera) => write!(f, "camera {camera} is not running"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
 #[derive(Clone)]
 pub struct ServerState {
-    pub ws_path: String,
+    pub cameras: Vec<String>,
+    pub fanout: Arc<FrameFanout>,
+    pub active_viewers: Arc<AtomicUsize>,
+    pub remote_viewers: Arc<AtomicUsize>,
+    pub mjpeg_max_fps: u32,
+    pub frame_cache: Arc<FrameCache>,
+    pub bandwidth: Arc<BandwidthMeter>,
+    /// Remote viewers are metered unless the stream is served to the LAN only.
+    pub tunnel: TunnelKind,
+    /// Set if requests through the tunnel need a stream token.
+    pub tokens: Option<Arc<StreamTokens>>,
+    /// Shared program state reported by `/api/status`.
+    pub variables: Variables,
 }
 
-#[derive(Debug, Default, Clone)]
-pub struct CameraConfig {
-    pub interval: (u32, u32),
-    pub width: u32,
-    pub height: u32,
-    pub fourcc: [u8; 4],
-    pub buf_size: u32,
-    pub dev_idx: u8,
-}
-#[derive(Default, Debug, Clone)]
+/// Monthly totals of [`Variables::bandwidth`], stored in [`Config::data_dir`].
+const BANDWIDTH_FILE: &str = "bandwidth.toml";
+/// Bot users managed at runtime, stored in [`Config::data_dir`].
+const USERS_FILE: &str = "users.toml";
+/// Notification settings changed by `/notify`, stored in [`Config::data_dir`].
+const NOTIFY_FILE: &str = "notify.toml";
+/// Motion detection state changed by `/arm`, `/disarm` and `/alerts`, stored in [`Config::data_dir`].
+const MOTION_FILE: &str = "motion.toml";
+
+#[derive(Debug, Clone)]
 pub struct Variables {
-    pub camera_config: CameraConfig,
-    pub ngrok_auth_token: String,
-    pub ngrok_domain: String,
-    pub server_address: String,
-    pub telegram_config: TelegramConfig,
-    pub is_ngrok_started: Arc<RwLock<bool>>,
-    pub ngrok_shutdown_tx: Arc<Mutex<Option<oneshot::Sender<()>>>>,
-}
-
-#[derive(Debug, Default, Clone)]
-pub struct TelegramConfig {
-    pub token: String,
-    pub admin_user_id: i64,
-    pub allowed_user_ids: Vec<i64>,
-}
-pub fn init_config_by_env(args: Vec<(String, String)>) -> Variables {
-    let mut hashmap = std::collections::HashMap::new();
-    args.iter().for_each(|(k, v)| {
-        hashmap.insert(k.clone(), v.clone());
-    });
-
-    let admin_user_id = hashmap
-        .get("TELEGRAM_ADMIN_USER_ID")
-        .expect("TELEGRAM_ADMIN_USER_ID is not set")
-        .parse()
-        .unwrap();
-    let allowed_user_ids_config = hashmap
-        .get("TELEGRAM_ALLOWED_USER_IDS")
-        .expect("TELEGRAM_ALLOWED_USER_IDS is not set");
-    let mut allowed_user_ids: Vec<i64> = allowed_user_ids_config.split(',').map(|x| x.parse().unwrap()).collect();
-    allowed_user_ids.extend(vec![admin_user_id]);
-
-    let variables = Variables {
-        camera_config: CameraConfig {
-            interval: DEFAULT_CAMERA_INTERVAL,
-            width: hashmap
-                .get("CAMERA_WIDTH")
-                .and_then(|w| w.parse::<u32>().ok())
-                .unwrap_or(DEFAULT_CAMERA_WIDTH),
-            height: hashmap
-                .get("CAMERA_HEIGHT")
-                .and_then(|h| h.parse::<u32>().ok())
-                .unwrap_or(DEFAULT_CAMERA_HEIGHT),
-            fourcc: DEFAULT_CAMERA_FOURCC,
-            buf_size: BUF_COUNT,
-            dev_idx: hashmap
-                .get("CAMERA_DEV_IDX")
-                .and_then(|h| h.parse::<u8>().ok())
-                .unwrap_or(DEFAULT_CAMERA_DEV_IDX),
-        },
-        ngrok_auth_token: hashmap
-            .get("NGROK_AUTH_TOKEN")
-            .expect("NGROK_AUTH_TOKEN is not set")
-            .to_string(),
-        ngrok_domain: hashmap.get("NGROK_DOMAIN").expect("NGROK_DOMAIN is not set").to_string(),
-        server_address: hashmap
-            .get("SERVER_ADDRESS")
-            .unwrap_or(&"localhost:8080".to_string())
-            .to_string(),
-        telegram_config: TelegramConfig {
-            token: hashmap.get("TELEGRAM_TOKEN").expect("TELEGRAM_TOKEN is not set").to_string(),
-            admin_user_id: admin_user_id,
-            allowed_user_ids: allowed_user_ids,
-        },
-        is_ngrok_started: Arc::new(RwLock::new(false)),
-        ngrok_shutdown_tx: Arc::new(Mutex::new(None)),
-    };
-
-    variables
+    pub config: Config,
+    /// Exposes the stream for `/getvideo`, selected by [`Config::tunnel`].
+    pub tunnel: Arc<dyn TunnelProvider>,
+    /// Incremented on every tunnel start, lets a watchdog notice that its session is over.
+    pub tunnel_sessions: Arc<AtomicU64>,
+    /// Number of currently connected stream viewers.
+    pub active_viewers: Arc<AtomicUsize>,
+    /// Part of [`Variables::active_viewers`] connected through the tunnel.
+    pub remote_viewers: Arc<AtomicUsize>,
+    pub frame_cache: Arc<FrameCache>,
+    /// Bytes streamed to remote viewers per month.
+    pub bandwidth: Arc<BandwidthMeter>,
+    /// Signs the stream URLs sent by `/getvideo`.
+    pub stream_tokens: Arc<StreamTokens>,
+    /// Bot users and their roles.
+    pub users: Arc<UserStore>,
+    /// Policy of the admin notifications.
+    pub notifier: Arc<Notifier>,
+    /// The last seconds of frames of every camera.
+    pub preroll: Arc<PrerollBuffer>,
+    /// Segments of the recording.
+    pub recordings: Arc<SegmentIndex>,
+    /// Whether motion is detected and who gets the alerts.
+    pub motion: Arc<MotionState>,
+    /// Capture statistics per camera.
+    pub camera_stats: Arc<PipelineStats>,
+    /// Start time of the program.
+    pub started: Instant,
+}
+
+impl Variables {
+    pub fn new(config: Config) -> Self {
+        Self {
+            frame_cache: Arc::new(FrameCache::new(&config.camera_names())),
+            camera_stats: Arc::new(PipelineStats::new(&config.camera_names())),
+            preroll: Arc::new(PrerollBuffer::new(&config.camera_names(), &config.preroll)),
+            tunnel: tunnel_provider(&config),
+            recordings: Arc::new(SegmentIndex::open(config.recording_dir())),
+            stream_tokens: Arc::new(StreamTokens::new(&config.access)),
+            users: Arc::new(UserStore::open(config.data_dir.join(USERS_FILE), &config.telegram_config)),
+            notifier: Arc::new(Notifier::open(
+                config.data_dir.join(NOTIFY_FILE),
+                &config.notify,
+                config.telegram_config.admin_user_id,
+            )),
+            motion: Arc::new(MotionState::open(
+                config.data_dir.join(MOTION_FILE),
+                &config.motion,
+                config.telegram_config.admin_user_id,
+            )),
+            bandwidth: Arc::new(BandwidthMeter::open(
+                config.data_dir.join(BANDWIDTH_FILE),
+                config.bandwidth.clone(),
+            )),
+            tunnel_sessions: Arc::new(AtomicU64::new(0)),
+            config,
+            active_viewers: Arc::new(AtomicUsize::new(0)),
+            remote_viewers: Arc::new(AtomicUsize::new(0)),
+            started: Instant::now(),
+        }
+    }
+}
+
+/// Writes a file through a temporary one, a power loss must not leave it truncated.
+pub fn write_file_atomic(path: &Path, content: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, content)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Calendar date (UTC) of `time` as year, month and day.
+pub fn utc_date(time: SystemTime) -> (i64, u32, u32) {
+    let days = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs() / 86_400;
+    // civil from days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month as u32, day as u32)
+}
+
+impl Default for Variables {
+    fn default() -> Self { Self::new(Config::default()) }
 }
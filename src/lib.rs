@@ -1,6 +1,19 @@
+pub mod access;
+pub mod avi;
+pub mod bandwidth;
+pub mod clip;
+pub mod config;
 pub mod core;
+pub mod motion;
+pub mod notify;
+pub mod preroll;
+pub mod recording;
+pub mod status;
+pub mod tunnel;
+pub mod users;
 pub mod workers;
 
 pub mod prelude {
+    pub use super::config::*;
     pub use super::core::*;
 }
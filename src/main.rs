@@ -3,6 +3,7 @@ use inst_upd::workers::*;
 use roboplc::controller::*;
 use roboplc::rvideo;
 use std::time::Duration;
+use tracing::info;
 
 const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
@@ -10,6 +11,10 @@ const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv::dotenv().ok();
     roboplc::setup_panic();
+    if dotenv::var("ROBOPLC_SIMULATED").is_ok() {
+        // no RT scheduling / CPU affinity, e.g. on a dev machine or in CI
+        roboplc::thread_rt::set_simulated();
+    }
     roboplc::configure_logger(
         dotenv::var("RUST_LOG")
             .unwrap_or_else(|_| "info".to_string())
@@ -17,21 +22,47 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .unwrap(),
     );
 
-    let variables = init_config_by_env(dotenv::vars().collect());
+    let config = match Config::load(dotenv::vars().collect()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    if std::env::args().any(|arg| arg == "--dump-config") {
+        // prints the effective settings (file + environment), secrets are hidden
+        print!("{}", config.to_toml_redacted()?);
+        return Ok(());
+    }
+    let variables = Variables::new(config);
 
-    // @todo move to the worker as function
-    let rvideo_stream = rvideo::add_stream(
-        rvideo::Format::MJpeg,
-        variables.camera_config.width as u16,
-        variables.camera_config.height as u16,
-    )?;
-
-    let detector_video = DetectorVideo::new_with_rvideo(rvideo_stream);
+    let mut detectors = Vec::with_capacity(variables.config.cameras.len());
+    for camera in &variables.config.cameras {
+        // @todo move to the worker as function
+        let rvideo_stream = rvideo::add_stream(rvideo::Format::MJpeg, camera.width as u16, camera.height as u16)?;
+        info!(camera = camera.name, stream_id = rvideo_stream.id(), "rvideo stream added");
+        detectors.push(DetectorVideo::new_with_rvideo(camera.clone(), rvideo_stream));
+    }
 
     let mut controller: Controller<WorkerMessage, Variables> = Controller::new_with_variables(variables);
 
     controller.spawn_worker(RvideoSrv {})?;
-    controller.spawn_worker(detector_video)?;
+    for detector_video in detectors {
+        controller.spawn_worker(detector_video)?;
+    }
+    if !controller.variables().config.preroll.length.is_zero() {
+        controller.spawn_worker(PrerollWorker {})?;
+    }
+    if controller.variables().config.recording.enabled {
+        for camera in controller.variables().config.camera_names() {
+            controller.spawn_worker(RecorderWorker::new(&camera))?;
+        }
+    }
+    if controller.variables().config.motion.enabled {
+        for camera in controller.variables().config.camera_names() {
+            controller.spawn_worker(MotionDetector::new(&camera))?;
+        }
+    }
     controller.spawn_worker(BotWorker {})?;
     controller.spawn_worker(WebSocketWorker {})?;
     // register SIGINT and SIGTERM signals with max shutdown timeout
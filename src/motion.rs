@@ -0,0 +1,330 @@
+use crate::config::{MotionConfig, MotionZone, ZoneKind};
+use crate::core::write_file_atomic;
+use jpeg_decoder::{Decoder, PixelFormat};
+use jpeg_encoder::{ColorType, Encoder};
+use roboplc::locking::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Frames are decoded to at least this size, the decoder scales them down almost for free.
+const ANALYSIS_WIDTH: u16 = 160;
+const ANALYSIS_HEIGHT: u16 = 120;
+/// JPEG quality of the `/zones` snapshot.
+const ZONES_QUALITY: u8 = 85;
+const DETECT_COLOR: [u8; 3] = [0, 200, 0];
+const IGNORE_COLOR: [u8; 3] = [220, 0, 0];
+
+/// Grayscale picture the motion is detected on.
+#[derive(Debug, Clone)]
+pub struct LumaFrame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl LumaFrame {
+    /// Decodes a JPEG frame scaled down to about [`ANALYSIS_WIDTH`] x [`ANALYSIS_HEIGHT`].
+    pub fn decode(jpeg: &[u8]) -> Result<Self, jpeg_decoder::Error> {
+        let mut decoder = Decoder::new(jpeg);
+        let (width, height) = decoder.scale(ANALYSIS_WIDTH, ANALYSIS_HEIGHT)?;
+        let data = decoder.decode()?;
+        let format = decoder.info().map(|info| info.pixel_format);
+        let pixels = match format {
+            Some(PixelFormat::L8) => data,
+            // big-endian samples, the high byte is enough
+            Some(PixelFormat::L16) => data.chunks_exact(2).map(|sample| sample[0]).collect(),
+            Some(PixelFormat::RGB24) => data
+                .chunks_exact(3)
+                .map(|rgb| ((u32::from(rgb[0]) * 77 + u32::from(rgb[1]) * 150 + u32::from(rgb[2]) * 29) >> 8) as u8)
+                .collect(),
+            _ => return Err(jpeg_decoder::Error::Format("unsupported pixel format".to_string())),
+        };
+        Ok(Self {
+            width: usize::from(width),
+            height: usize::from(height),
+            pixels,
+        })
+    }
+}
+
+/// Pixels of a frame which the motion detection looks at.
+#[derive(Debug)]
+struct ZoneMask {
+    width: usize,
+    height: usize,
+    analyzed: Vec<bool>,
+    count: usize,
+}
+
+/// Compares every frame with the previous one and measures how much of the picture changed,
+/// within the zones of the camera.
+#[derive(Debug)]
+pub struct MotionAnalyzer {
+    /// Smallest brightness change of a pixel which counts as motion.
+    threshold: u8,
+    zones: Vec<MotionZone>,
+    mask: Option<ZoneMask>,
+    previous: Option<LumaFrame>,
+}
+
+impl MotionAnalyzer {
+    /// `sensitivity` from 1 to 100, at 100 a pixel changed by 2 of 255 brightness levels counts.
+    pub fn new(sensitivity: u8, zones: Vec<MotionZone>) -> Self {
+        let sensitivity = u32::from(sensitivity.clamp(1, 100));
+        Self {
+            threshold: ((101 - sensitivity) * 255 / 100).max(1) as u8,
+            zones,
+            mask: None,
+            previous: None,
+        }
+    }
+
+    /// Share of the analyzed pixels in percent which changed since the previous frame, `None` for
+    /// the first frame, after the frame size has changed and if the zones leave no pixels.
+    pub fn analyze(&mut self, frame: LumaFrame) -> Option<f64> {
+        let previous = self.previous.replace(frame);
+        let (previous, current) = (previous?, self.previous.as_ref()?);
+        if previous.width != current.width || previous.height != current.height {
+            return None;
+        }
+        if self
+            .mask
+            .as_ref()
+            .is_none_or(|mask| mask.width != current.width || mask.height != current.height)
+        {
+            let analyzed = zone_mask(&self.zones, current.width, current.height);
+            self.mask = Some(ZoneMask {
+                width: current.width,
+                height: current.height,
+                count: analyzed.iter().filter(|&&analyzed| analyzed).count(),
+                analyzed,
+            });
+        }
+        let mask = self.mask.as_ref()?;
+        if mask.count == 0 {
+            return None;
+        }
+        let changed = previous
+            .pixels
+            .iter()
+            .zip(&current.pixels)
+            .zip(&mask.analyzed)
+            .filter(|((a, b), &analyzed)| analyzed && a.abs_diff(**b) >= self.threshold)
+            .count();
+        Some(changed as f64 * 100.0 / mask.count as f64)
+    }
+
+    /// Forgets the previous frame, e.g. while the detection is disarmed.
+    pub fn reset(&mut self) { self.previous = None; }
+}
+
+/// Returns true if motion at the point (coordinates from 0 to 1) counts: it is inside a
+/// detection zone, or the camera has none, and outside of all ignore zones.
+pub fn is_analyzed(zones: &[MotionZone], x: f64, y: f64) -> bool {
+    let mut detect = zones.iter().filter(|zone| zone.kind == ZoneKind::Detect).peekable();
+    let detected = detect.peek().is_none() || detect.any(|zone| zone.contains(x, y));
+    detected && !zones.iter().any(|zone| zone.kind == ZoneKind::Ignore && zone.contains(x, y))
+}
+
+/// Center of the `i`-th pixel of a frame, counted row by row, in coordinates from 0 to 1.
+fn pixel_center(i: usize, width: usize, height: usize) -> (f64, f64) {
+    (
+        ((i % width) as f64 + 0.5) / width as f64,
+        ((i / width) as f64 + 0.5) / height as f64,
+    )
+}
+
+/// [`is_analyzed`] for every pixel of a frame.
+fn zone_mask(zones: &[MotionZone], width: usize, height: usize) -> Vec<bool> {
+    (0 .. width * height)
+        .map(|i| {
+            let (x, y) = pixel_center(i, width, height);
+            is_analyzed(zones, x, y)
+        })
+        .collect()
+}
+
+/// Draws the zones on a JPEG frame: the parts which are not analyzed are darkened, the ignore
+/// zones tinted red and the zones outlined, detection zones in green and ignore zones in red.
+pub fn draw_zones(jpeg: &[u8], zones: &[MotionZone]) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut decoder = Decoder::new(jpeg);
+    let data = decoder.decode()?;
+    let info = decoder.info().ok_or("no JPEG frame information")?;
+    let mut rgb = match info.pixel_format {
+        PixelFormat::RGB24 => data,
+        PixelFormat::L8 => data.iter().flat_map(|&luma| [luma; 3]).collect(),
+        _ => return Err("unsupported pixel format".into()),
+    };
+    let (width, height) = (usize::from(info.width), usize::from(info.height));
+    let analyzed = zone_mask(zones, width, height);
+    let areas: Vec<Vec<bool>> = zones
+        .iter()
+        .map(|zone| {
+            (0 .. width * height)
+                .map(|i| {
+                    let (x, y) = pixel_center(i, width, height);
+                    zone.contains(x, y)
+                })
+                .collect()
+        })
+        .collect();
+    let inside = |zone: usize, i: usize| areas[zone][i];
+    let border = (width.max(height) / 320).max(2);
+    for (i, pixel) in rgb.chunks_exact_mut(3).enumerate() {
+        let (x, y) = (i % width, i / width);
+        if !analyzed[i] {
+            for channel in pixel.iter_mut() {
+                *channel = (u16::from(*channel) * 2 / 5) as u8;
+            }
+        }
+        for (zone, area) in zones.iter().enumerate() {
+            if !inside(zone, i) {
+                continue;
+            }
+            let color = match area.kind {
+                ZoneKind::Detect => DETECT_COLOR,
+                ZoneKind::Ignore => IGNORE_COLOR,
+            };
+            let neighbors = [
+                x.checked_sub(border).map(|x| y * width + x),
+                Some(x + border).filter(|&x| x < width).map(|x| y * width + x),
+                y.checked_sub(border).map(|y| y * width + x),
+                Some(y + border).filter(|&y| y < height).map(|y| y * width + x),
+            ];
+            let edge = neighbors.iter().any(|neighbor| neighbor.is_none_or(|n| !inside(zone, n)));
+            if edge {
+                pixel.copy_from_slice(&color);
+            } else if area.kind == ZoneKind::Ignore {
+                for (channel, tint) in pixel.iter_mut().zip(color) {
+                    *channel = ((u16::from(*channel) * 3 + u16::from(tint)) / 4) as u8;
+                }
+            }
+        }
+    }
+    let mut out = Vec::new();
+    Encoder::new(&mut out, ZONES_QUALITY).encode(&rgb, info.width, info.height, ColorType::Rgb)?;
+    Ok(out)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MotionSettings {
+    armed: bool,
+    /// Users who get the alerts.
+    #[serde(default)]
+    subscribers: Vec<i64>,
+}
+
+/// Whether the motion detection is armed and who gets the alerts.
+///
+/// Starts as [`MotionConfig::armed`] with the admin subscribed, the changes made by `/arm`,
+/// `/disarm` and `/alerts` are stored on disk and replace it.
+#[derive(Debug)]
+pub struct MotionState {
+    path: PathBuf,
+    settings: Mutex<MotionSettings>,
+    /// Cameras with an alert clip being recorded.
+    clips: Mutex<HashSet<String>>,
+}
+
+impl MotionState {
+    pub fn open(path: PathBuf, config: &MotionConfig, admin_user_id: i64) -> Self {
+        let configured = MotionSettings {
+            armed: config.armed,
+            subscribers: vec![admin_user_id],
+        };
+        let settings = match Self::load(&path) {
+            Ok(Some(settings)) => settings,
+            Ok(None) => configured,
+            Err(e) => {
+                warn!("Failed to read motion detection state from {}: {}", path.display(), e);
+                configured
+            }
+        };
+        Self {
+            path,
+            settings: Mutex::new(settings),
+            clips: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn load(path: &Path) -> Result<Option<MotionSettings>, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(toml::from_str(&std::fs::read_to_string(path)?)?))
+    }
+
+    /// Changes the settings and stores them, nothing is changed if they can not be stored.
+    fn update(&self, f: impl FnOnce(&mut MotionSettings)) -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = self.settings.lock();
+        let mut changed = settings.clone();
+        f(&mut changed);
+        write_file_atomic(&self.path, &toml::to_string(&changed)?)?;
+        *settings = changed;
+        Ok(())
+    }
+
+    pub fn is_armed(&self) -> bool { self.settings.lock().armed }
+
+    pub fn set_armed(&self, armed: bool) -> Result<(), Box<dyn std::error::Error>> {
+        self.update(|settings| settings.armed = armed)
+    }
+
+    pub fn subscribers(&self) -> Vec<i64> { self.settings.lock().subscribers.clone() }
+
+    pub fn is_subscribed(&self, user_id: i64) -> bool { self.settings.lock().subscribers.contains(&user_id) }
+
+    /// Marks an alert clip of the camera as being recorded, false if one is recorded already.
+    pub fn start_clip(&self, camera: &str) -> bool { self.clips.lock().insert(camera.to_string()) }
+
+    pub fn finish_clip(&self, camera: &str) { self.clips.lock().remove(camera); }
+
+    /// Turns the alerts of the user on or off.
+    pub fn subscribe(&self, user_id: i64, on: bool) -> Result<(), Box<dyn std::error::Error>> {
+        self.update(|settings| {
+            settings.subscribers.retain(|&id| id != user_id);
+            if on {
+                settings.subscribers.push(user_id);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zones(value: &str) -> Vec<MotionZone> { value.split(';').map(|zone| zone.parse().unwrap()).collect() }
+
+    #[test]
+    fn everything_is_analyzed_without_zones() {
+        assert!(is_analyzed(&[], 0.0, 0.0));
+        assert!(is_analyzed(&[], 0.5, 0.5));
+        assert!(is_analyzed(&[], 1.0, 1.0));
+    }
+
+    #[test]
+    fn only_detection_zones_are_analyzed() {
+        let zones = zones("detect:0,0 0.5,0.5");
+        assert!(is_analyzed(&zones, 0.25, 0.25));
+        assert!(!is_analyzed(&zones, 0.75, 0.75));
+    }
+
+    #[test]
+    fn ignore_zones_override_detection_zones() {
+        let zones = zones("detect:0,0 0.5,0.5;ignore:0.25,0.25 1,1");
+        assert!(is_analyzed(&zones, 0.1, 0.1));
+        assert!(!is_analyzed(&zones, 0.4, 0.4));
+        assert!(!is_analyzed(&zones, 0.75, 0.75));
+        assert!(!is_analyzed(&zones, 0.75, 0.1));
+    }
+
+    #[test]
+    fn ignore_zones_alone_leave_the_rest_analyzed() {
+        let zones = zones("ignore:0,0 0.5,1");
+        assert!(!is_analyzed(&zones, 0.25, 0.5));
+        assert!(is_analyzed(&zones, 0.75, 0.5));
+    }
+}
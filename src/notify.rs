@@ -0,0 +1,149 @@
+use crate::config::{NotifyConfig, NotifyEvent};
+use crate::core::write_file_atomic;
+use roboplc::locking::Mutex;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// Longest list of notifications in a digest, the rest is only counted.
+const MAX_DIGEST_LINES: usize = 20;
+
+#[derive(Debug)]
+struct NotifierState {
+    settings: NotifyConfig,
+    /// Notifications held for the next digest.
+    pending: Vec<String>,
+    /// Held notifications beyond [`MAX_DIGEST_LINES`].
+    skipped: usize,
+    last_sent: Option<Instant>,
+}
+
+/// Decides which bot activity the admin is notified about and when.
+///
+/// The settings come from the config, `/notify` changes are stored on disk and replace them.
+#[derive(Debug)]
+pub struct Notifier {
+    path: PathBuf,
+    admin_user_id: i64,
+    configured: NotifyConfig,
+    state: Mutex<NotifierState>,
+}
+
+impl Notifier {
+    /// Loads the settings changed by `/notify` from `path`, if any.
+    pub fn open(path: PathBuf, config: &NotifyConfig, admin_user_id: i64) -> Self {
+        let settings = match Self::load(&path) {
+            Ok(Some(settings)) => settings,
+            Ok(None) => config.clone(),
+            Err(e) => {
+                warn!("Failed to read notification settings from {}: {}", path.display(), e);
+                config.clone()
+            }
+        };
+        Self {
+            path,
+            admin_user_id,
+            configured: config.clone(),
+            state: Mutex::new(NotifierState {
+                settings,
+                pending: Vec::new(),
+                skipped: 0,
+                last_sent: None,
+            }),
+        }
+    }
+
+    fn load(path: &Path) -> Result<Option<NotifyConfig>, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(toml::from_str(&std::fs::read_to_string(path)?)?))
+    }
+
+    pub fn settings(&self) -> NotifyConfig { self.state.lock().settings.clone() }
+
+    /// Changes the settings and stores them, nothing is changed if they can not be stored.
+    pub fn update(&self, f: impl FnOnce(&mut NotifyConfig)) -> Result<(), Box<dyn std::error::Error>> {
+        let mut state = self.state.lock();
+        let mut settings = state.settings.clone();
+        f(&mut settings);
+        write_file_atomic(&self.path, &toml::to_string(&settings)?)?;
+        state.settings = settings;
+        Ok(())
+    }
+
+    /// Drops the stored settings and returns to the config.
+    pub fn reset(&self) -> std::io::Result<()> {
+        let mut state = self.state.lock();
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        state.settings = self.configured.clone();
+        Ok(())
+    }
+
+    /// Returns the text to send to the admin now, `None` if the event is off or the text is held for a digest.
+    ///
+    /// `actor` is the user who caused the event, if any.
+    pub fn notify(&self, event: NotifyEvent, actor: Option<i64>, text: &str) -> Option<String> {
+        let mut state = self.state.lock();
+        if !state.settings.events.contains(&event) {
+            return None;
+        }
+        if actor == Some(self.admin_user_id) && !state.settings.own_actions {
+            return None;
+        }
+        if state.is_quiet() || state.in_digest_window() {
+            debug!(%event, "Admin notification held for a digest");
+            if state.pending.len() < MAX_DIGEST_LINES {
+                state.pending.push(text.to_string());
+            } else {
+                state.skipped += 1;
+            }
+            return None;
+        }
+        state.last_sent = Some(Instant::now());
+        Some(text.to_string())
+    }
+
+    /// Returns the held notifications once the quiet hours and the digest window are over.
+    pub fn digest(&self) -> Option<String> {
+        let mut state = self.state.lock();
+        if state.pending.is_empty() || state.is_quiet() || state.in_digest_window() {
+            return None;
+        }
+        state.last_sent = Some(Instant::now());
+        let count = state.pending.len() + state.skipped;
+        let mut pending = std::mem::take(&mut state.pending);
+        let skipped = std::mem::take(&mut state.skipped);
+        if count == 1 {
+            return pending.pop();
+        }
+        let mut text = format!("{count} notifications:");
+        for line in pending {
+            text.push_str("\n- ");
+            text.push_str(&line);
+        }
+        if skipped > 0 {
+            text.push_str(&format!("\n... and {skipped} more"));
+        }
+        Some(text)
+    }
+}
+
+impl NotifierState {
+    fn is_quiet(&self) -> bool {
+        let Some(quiet_hours) = self.settings.quiet_hours else {
+            return false;
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+        let local = now + i64::from(self.settings.utc_offset_minutes) * 60;
+        quiet_hours.contains((local.rem_euclid(86_400) / 60) as u16)
+    }
+
+    fn in_digest_window(&self) -> bool {
+        self.last_sent
+            .is_some_and(|sent| sent.elapsed() < self.settings.digest_window)
+    }
+}
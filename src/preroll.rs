@@ -0,0 +1,74 @@
+use crate::bandwidth::BYTES_PER_MB;
+use crate::config::PrerollConfig;
+use roboplc::locking::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A frame kept in [`PrerollBuffer`].
+#[derive(Debug, Clone)]
+pub struct BufferedFrame {
+    pub data: Arc<Vec<u8>>,
+    pub received: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Ring {
+    frames: VecDeque<BufferedFrame>,
+    bytes: u64,
+}
+
+/// The last seconds of frames of every camera, lets clips and event recordings start before
+/// the moment they were asked for. Filled by [`crate::workers::PrerollWorker`].
+///
+/// Besides its length, the buffer of every camera is limited to its share of
+/// [`PrerollConfig::max_mb`], so that a high resolution or frame rate can not exhaust the memory.
+#[derive(Debug)]
+pub struct PrerollBuffer {
+    length: Duration,
+    max_bytes: u64,
+    cameras: HashMap<String, Mutex<Ring>>,
+}
+
+impl PrerollBuffer {
+    pub fn new(cameras: &[String], config: &PrerollConfig) -> Self {
+        Self {
+            length: config.length,
+            max_bytes: config.max_mb * BYTES_PER_MB / cameras.len().max(1) as u64,
+            cameras: cameras
+                .iter()
+                .map(|camera| (camera.clone(), Mutex::new(Ring::default())))
+                .collect(),
+        }
+    }
+
+    pub fn length(&self) -> Duration { self.length }
+
+    pub fn push(&self, camera: &str, data: Vec<u8>) {
+        let Some(ring) = self.cameras.get(camera) else {
+            return;
+        };
+        let mut ring = ring.lock();
+        let now = Instant::now();
+        ring.bytes += data.len() as u64;
+        ring.frames.push_back(BufferedFrame {
+            data: Arc::new(data),
+            received: now,
+        });
+        while let Some(oldest) = ring.frames.front() {
+            if now.duration_since(oldest.received) <= self.length && ring.bytes <= self.max_bytes {
+                break;
+            }
+            ring.bytes -= oldest.data.len() as u64;
+            ring.frames.pop_front();
+        }
+    }
+
+    /// Buffered frames of the camera, oldest first.
+    pub fn frames(&self, camera: &str) -> Vec<BufferedFrame> {
+        self.cameras
+            .get(camera)
+            .map(|ring| ring.lock().frames.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
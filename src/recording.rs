@@ -0,0 +1,137 @@
+use crate::core::write_file_atomic;
+use roboplc::locking::Mutex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Index of the segments, stored in the recording directory.
+const INDEX_FILE: &str = "index.toml";
+
+/// A finished segment file of the recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Segment {
+    pub camera: String,
+    /// Path relative to the recording directory, like `main/20240915-120000-250.avi`.
+    pub file: String,
+    /// Capture time of the first frame, Unix time in milliseconds.
+    pub start_ms: u64,
+    /// End of the segment, Unix time in milliseconds.
+    pub end_ms: u64,
+    pub frames: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IndexFile {
+    #[serde(default, rename = "segment")]
+    segments: Vec<Segment>,
+}
+
+/// Segments written by [`crate::workers::RecorderWorker`] ordered by their start time, lets
+/// them be looked up by time.
+#[derive(Debug)]
+pub struct SegmentIndex {
+    dir: PathBuf,
+    segments: Mutex<Vec<Segment>>,
+}
+
+impl SegmentIndex {
+    /// Loads the index from `dir`, segments whose files are gone are dropped.
+    pub fn open(dir: PathBuf) -> Self {
+        let path = dir.join(INDEX_FILE);
+        let mut segments = match Self::load(&path) {
+            Ok(index) => index.segments,
+            Err(e) => {
+                warn!("Failed to read the recording index {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+        segments.retain(|segment| dir.join(&segment.file).exists());
+        segments.sort_by_key(|segment| segment.start_ms);
+        Self {
+            dir,
+            segments: Mutex::new(segments),
+        }
+    }
+
+    fn load(path: &Path) -> Result<IndexFile, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(IndexFile::default());
+        }
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn dir(&self) -> &Path { &self.dir }
+
+    pub fn add(&self, segment: Segment) {
+        let mut segments = self.segments.lock();
+        let pos = segments.partition_point(|s| s.start_ms <= segment.start_ms);
+        segments.insert(pos, segment);
+        self.save(&segments);
+    }
+
+    /// Segments overlapping the time range (Unix time in milliseconds), of all cameras if
+    /// `camera` is not set.
+    pub fn find(&self, camera: Option<&str>, from_ms: u64, to_ms: u64) -> Vec<Segment> {
+        self.segments
+            .lock()
+            .iter()
+            .filter(|s| camera.is_none_or(|camera| s.camera == camera))
+            .filter(|s| s.start_ms <= to_ms && s.end_ms >= from_ms)
+            .cloned()
+            .collect()
+    }
+
+    pub fn total_bytes(&self) -> u64 { self.segments.lock().iter().map(|s| s.bytes).sum() }
+
+    /// Deletes the segments older than `max_age` and the oldest ones while all of them take more
+    /// than `max_bytes`, zero disables either limit. Returns the number of deleted segments.
+    pub fn enforce_retention(&self, max_age: Duration, max_bytes: u64) -> usize {
+        let mut segments = self.segments.lock();
+        let oldest_kept = if max_age.is_zero() {
+            0
+        } else {
+            unix_millis(SystemTime::now()).saturating_sub(max_age.as_millis() as u64)
+        };
+        let mut total: u64 = segments.iter().map(|s| s.bytes).sum();
+        let mut expired = 0;
+        for segment in segments.iter() {
+            let too_large = max_bytes > 0 && total > max_bytes;
+            if segment.end_ms >= oldest_kept && !too_large {
+                break;
+            }
+            total -= segment.bytes;
+            expired += 1;
+        }
+        if expired == 0 {
+            return 0;
+        }
+        for segment in segments.drain(.. expired) {
+            let path = self.dir.join(&segment.file);
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    warn!("Failed to delete the segment {}: {}", path.display(), e);
+                }
+                _ => info!(camera = segment.camera, "Segment {} deleted", segment.file),
+            }
+        }
+        self.save(&segments);
+        expired
+    }
+
+    fn save(&self, segments: &[Segment]) {
+        let index = IndexFile {
+            segments: segments.to_vec(),
+        };
+        let path = self.dir.join(INDEX_FILE);
+        let saved = toml::to_string(&index)
+            .map_err(|e| e.to_string())
+            .and_then(|content| write_file_atomic(&path, &content).map_err(|e| e.to_string()));
+        if let Err(e) = saved {
+            warn!("Failed to save the recording index {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub fn unix_millis(time: SystemTime) -> u64 { time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64 }
@@ -0,0 +1,239 @@
+use crate::bandwidth::format_mb;
+use crate::core::Variables;
+use crate::tunnel::TunnelStatus;
+use nix::sys::statvfs::statvfs;
+use roboplc::locking::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+const CPU_TEMPERATURE_FILE: &str = "/sys/class/thermal/thermal_zone0/temp";
+const LOAD_AVERAGE_FILE: &str = "/proc/loadavg";
+const SYSTEM_UPTIME_FILE: &str = "/proc/uptime";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CameraState {
+    #[default]
+    Starting,
+    Running,
+    Failed,
+}
+
+impl fmt::Display for CameraState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraState::Starting => write!(f, "starting"),
+            CameraState::Running => write!(f, "running"),
+            CameraState::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Capture statistics of a camera, updated by [`crate::workers::DetectorVideo`].
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct CameraStats {
+    pub state: CameraState,
+    /// Why the camera has failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub source: String,
+    /// Format negotiated with the device, like `MJPG 640x480 30 fps`.
+    pub format: String,
+    /// Frame rate measured over the last few seconds.
+    pub fps: f64,
+    pub frames: u64,
+    pub bytes: u64,
+}
+
+/// [`CameraStats`] of all the cameras.
+#[derive(Debug)]
+pub struct PipelineStats {
+    cameras: HashMap<String, Mutex<CameraStats>>,
+}
+
+impl PipelineStats {
+    pub fn new(cameras: &[String]) -> Self {
+        Self {
+            cameras: cameras
+                .iter()
+                .map(|camera| (camera.clone(), Mutex::new(CameraStats::default())))
+                .collect(),
+        }
+    }
+
+    pub fn update(&self, camera: &str, f: impl FnOnce(&mut CameraStats)) {
+        if let Some(stats) = self.cameras.get(camera) {
+            f(&mut stats.lock());
+        }
+    }
+
+    pub fn get(&self, camera: &str) -> Option<CameraStats> { self.cameras.get(camera).map(|stats| stats.lock().clone()) }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CameraStatus {
+    pub name: String,
+    #[serde(flatten)]
+    pub stats: CameraStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TunnelReport {
+    pub backend: &'static str,
+    pub status: TunnelStatus,
+    /// Set while the tunnel is running.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiskUsage {
+    pub free_bytes: u64,
+    pub total_bytes: u64,
+}
+
+/// Health of the device and the pipeline, reported by `/status` and `/api/status`.
+///
+/// The system values are `None` where the host does not provide them.
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    pub cameras: Vec<CameraStatus>,
+    pub active_viewers: usize,
+    pub tunnel: TunnelReport,
+    pub uptime_secs: u64,
+    pub system_uptime_secs: Option<u64>,
+    pub cpu_temperature_celsius: Option<f64>,
+    /// 1, 5 and 15 minutes load averages.
+    pub load_average: Option<[f64; 3]>,
+    /// Space of the file system of the data directory.
+    pub disk: Option<DiskUsage>,
+    pub bandwidth_used_bytes: u64,
+    /// Zero if unlimited.
+    pub bandwidth_budget_bytes: u64,
+}
+
+impl StatusReport {
+    pub fn collect(variables: &Variables) -> Self {
+        let tunnel = &variables.tunnel;
+        let status = tunnel.status();
+        Self {
+            cameras: variables
+                .config
+                .cameras
+                .iter()
+                .map(|camera| CameraStatus {
+                    name: camera.name.clone(),
+                    stats: variables.camera_stats.get(&camera.name).unwrap_or_default(),
+                })
+                .collect(),
+            active_viewers: variables.active_viewers.load(Ordering::SeqCst),
+            tunnel: TunnelReport {
+                backend: tunnel.name(),
+                status,
+                url: (status == TunnelStatus::Running).then(|| tunnel.url()),
+            },
+            uptime_secs: variables.started.elapsed().as_secs(),
+            system_uptime_secs: system_uptime(),
+            cpu_temperature_celsius: cpu_temperature(),
+            load_average: load_average(),
+            disk: disk_usage(&variables.config.data_dir),
+            bandwidth_used_bytes: variables.bandwidth.usage().bytes,
+            bandwidth_budget_bytes: variables.bandwidth.budget(),
+        }
+    }
+}
+
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for camera in &self.cameras {
+            let stats = &camera.stats;
+            write!(f, "Camera {}: {}", camera.name, stats.state)?;
+            if let Some(ref error) = stats.error {
+                write!(f, " ({error})")?;
+            }
+            writeln!(f)?;
+            writeln!(f, "  {}, {}", stats.source, stats.format)?;
+            writeln!(
+                f,
+                "  {:.1} fps, {} frames, {} processed",
+                stats.fps,
+                stats.frames,
+                format_mb(stats.bytes)
+            )?;
+        }
+        writeln!(f, "Viewers: {}", self.active_viewers)?;
+        write!(f, "Tunnel {}: {}", self.tunnel.backend, self.tunnel.status)?;
+        if let Some(ref url) = self.tunnel.url {
+            write!(f, " at {url}")?;
+        }
+        writeln!(f)?;
+        write!(f, "Uptime: {}", format_uptime(self.uptime_secs))?;
+        if let Some(secs) = self.system_uptime_secs {
+            write!(f, " (system {})", format_uptime(secs))?;
+        }
+        writeln!(f)?;
+        if let Some(temperature) = self.cpu_temperature_celsius {
+            writeln!(f, "CPU temperature: {temperature:.1} °C")?;
+        }
+        if let Some([load1, load5, load15]) = self.load_average {
+            writeln!(f, "Load: {load1:.2} {load5:.2} {load15:.2}")?;
+        }
+        if let Some(ref disk) = self.disk {
+            writeln!(
+                f,
+                "Free disk: {} of {}",
+                format_mb(disk.free_bytes),
+                format_mb(disk.total_bytes)
+            )?;
+        }
+        write!(f, "Data this month: {}", format_mb(self.bandwidth_used_bytes))?;
+        if self.bandwidth_budget_bytes > 0 {
+            write!(f, " of {}", format_mb(self.bandwidth_budget_bytes))?;
+        }
+        Ok(())
+    }
+}
+
+fn format_uptime(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86_400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{days} d {hours} h {minutes} min")
+    } else if hours > 0 {
+        format!("{hours} h {minutes} min")
+    } else {
+        format!("{minutes} min")
+    }
+}
+
+fn system_uptime() -> Option<u64> {
+    let content = std::fs::read_to_string(SYSTEM_UPTIME_FILE).ok()?;
+    let secs: f64 = content.split_whitespace().next()?.parse().ok()?;
+    Some(secs as u64)
+}
+
+fn cpu_temperature() -> Option<f64> {
+    // millidegrees Celsius
+    let content = std::fs::read_to_string(CPU_TEMPERATURE_FILE).ok()?;
+    content.trim().parse::<f64>().ok().map(|millis| millis / 1000.0)
+}
+
+fn load_average() -> Option<[f64; 3]> {
+    let content = std::fs::read_to_string(LOAD_AVERAGE_FILE).ok()?;
+    let mut values = content.split_whitespace().map(|value| value.parse::<f64>().ok());
+    Some([values.next()??, values.next()??, values.next()??])
+}
+
+/// The data directory may not exist yet, its closest existing parent is checked then.
+#[allow(clippy::useless_conversion)] // the block counts are 32 bit on some targets
+fn disk_usage(path: &Path) -> Option<DiskUsage> {
+    let path = path.ancestors().find(|path| path.exists())?;
+    let stat = statvfs(path).ok()?;
+    let block_size = u64::from(stat.fragment_size());
+    Some(DiskUsage {
+        free_bytes: u64::from(stat.blocks_available()) * block_size,
+        total_bytes: u64::from(stat.blocks()) * block_size,
+    })
+}
@@ -0,0 +1,362 @@
+use crate::config::{Config, ListenAddress, TunnelKind};
+use async_trait::async_trait;
+use ngrok::config::{OauthOptions, TunnelBuilder};
+use ngrok::prelude::{TunnelExt, UrlTunnel};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+use roboplc::locking::Mutex;
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::{Child, Command};
+use tokio::sync::oneshot;
+use tracing::{error, info, warn};
+
+const NGROK_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// A tunnel command exiting within this time after start is reported as failed.
+const COMMAND_STARTUP_CHECK: Duration = Duration::from_secs(2);
+/// How long the tunnel command may take to exit on SIGTERM before it is killed.
+const COMMAND_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// State of a tunnel as reported by [`TunnelProvider::status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelStatus {
+    Stopped,
+    Running,
+}
+
+impl fmt::Display for TunnelStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TunnelStatus::Stopped => write!(f, "stopped"),
+            TunnelStatus::Running => write!(f, "running"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TunnelError {
+    AlreadyRunning,
+    NotRunning,
+    Failed(String),
+}
+
+impl fmt::Display for TunnelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TunnelError::AlreadyRunning => write!(f, "tunnel is already running"),
+            TunnelError::NotRunning => write!(f, "tunnel is not running"),
+            TunnelError::Failed(reason) => write!(f, "tunnel failed: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for TunnelError {}
+
+/// Exposes the HTTP server outside of the local network, used by `/getvideo` and `/stopvideo`.
+#[async_trait]
+pub trait TunnelProvider: Send + Sync + fmt::Debug {
+    /// Short name of the backend, used in logs and messages.
+    fn name(&self) -> &'static str;
+
+    async fn start(&self) -> Result<(), TunnelError>;
+
+    async fn stop(&self) -> Result<(), TunnelError>;
+
+    fn status(&self) -> TunnelStatus;
+
+    /// Base URL the viewers open while the tunnel is running.
+    fn url(&self) -> String;
+}
+
+/// Creates the provider selected by [`Config::tunnel`].
+pub fn tunnel_provider(config: &Config) -> Arc<dyn TunnelProvider> {
+    match config.tunnel {
+        TunnelKind::Ngrok => Arc::new(NgrokTunnel::new(config)),
+        TunnelKind::Lan => Arc::new(LanTunnel::new(config)),
+        TunnelKind::Command => Arc::new(CommandTunnel::new(config)),
+    }
+}
+
+/// ngrok HTTP endpoint on a static domain.
+#[derive(Debug)]
+pub struct NgrokTunnel {
+    auth_token: String,
+    domain: String,
+    basic_auth: Option<(String, String)>,
+    oauth_provider: String,
+    oauth_allow: Vec<String>,
+    forward_to: ListenAddress,
+    url: String,
+    /// Serializes concurrent `/getvideo` requests while the session is being connected.
+    starting: tokio::sync::Mutex<()>,
+    running: Arc<Mutex<Option<RunningNgrok>>>,
+    sessions: AtomicU64,
+}
+
+#[derive(Debug)]
+struct RunningNgrok {
+    id: u64,
+    shutdown_tx: oneshot::Sender<()>,
+}
+
+impl NgrokTunnel {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            auth_token: config.ngrok_auth_token.clone(),
+            domain: config.ngrok_domain.clone(),
+            basic_auth: config
+                .ngrok_basic_auth
+                .split_once(':')
+                .map(|(user, password)| (user.to_string(), password.to_string())),
+            oauth_provider: config.ngrok_oauth_provider.clone(),
+            oauth_allow: config.ngrok_oauth_allow.clone(),
+            forward_to: config.ngrok_forward_to(),
+            url: config.public_url(),
+            starting: tokio::sync::Mutex::new(()),
+            running: Arc::new(Mutex::new(None)),
+            sessions: AtomicU64::new(0),
+        }
+    }
+
+    async fn listen(&self) -> Result<(ngrok::Session, ngrok::tunnel::HttpTunnel), TunnelError> {
+        let session = ngrok::Session::builder()
+            .authtoken(self.auth_token.clone())
+            .connect()
+            .await
+            .map_err(|e| TunnelError::Failed(format!("ngrok session: {e}")))?;
+        let mut endpoint = session.http_endpoint().compression().domain(self.domain.clone());
+        if let Some((ref user, ref password)) = self.basic_auth {
+            endpoint = endpoint.basic_auth(user, password);
+        }
+        if !self.oauth_provider.is_empty() {
+            let oauth = self
+                .oauth_allow
+                .iter()
+                .fold(OauthOptions::new(&self.oauth_provider), |oauth, allow| {
+                    match allow.strip_prefix('@') {
+                        Some(domain) => oauth.allow_domain(domain),
+                        None => oauth.allow_email(allow),
+                    }
+                });
+            endpoint = endpoint.oauth(oauth);
+        }
+        let tunnel = endpoint
+            .listen()
+            .await
+            .map_err(|e| TunnelError::Failed(format!("ngrok endpoint {}: {}", self.domain, e)))?;
+        Ok((session, tunnel))
+    }
+}
+
+#[async_trait]
+impl TunnelProvider for NgrokTunnel {
+    fn name(&self) -> &'static str { "ngrok" }
+
+    async fn start(&self) -> Result<(), TunnelError> {
+        let _starting = self.starting.lock().await;
+        if self.running.lock().is_some() {
+            return Err(TunnelError::AlreadyRunning);
+        }
+        let (session, tunnel) = tokio::time::timeout(NGROK_CONNECT_TIMEOUT, self.listen())
+            .await
+            .map_err(|_| TunnelError::Failed("ngrok connection timed out".to_string()))??;
+        info!("Ngrok tunnel established at: {}", tunnel.url());
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let id = self.sessions.fetch_add(1, Ordering::SeqCst);
+        *self.running.lock() = Some(RunningNgrok { id, shutdown_tx });
+        let running = self.running.clone();
+        let forward_to = self.forward_to.clone();
+        tokio::spawn(async move {
+            forward_ngrok(session, tunnel, forward_to, shutdown_rx).await;
+            // a newer session may be running already if this one was stopped
+            let mut running = running.lock();
+            if running.as_ref().is_some_and(|running| running.id == id) {
+                *running = None;
+            }
+        });
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), TunnelError> {
+        let running = self.running.lock().take().ok_or(TunnelError::NotRunning)?;
+        // the tunnel task may have exited already
+        let _ = running.shutdown_tx.send(());
+        Ok(())
+    }
+
+    fn status(&self) -> TunnelStatus {
+        if self.running.lock().is_some() {
+            TunnelStatus::Running
+        } else {
+            TunnelStatus::Stopped
+        }
+    }
+
+    fn url(&self) -> String { self.url.clone() }
+}
+
+async fn forward_ngrok(
+    mut session: ngrok::Session,
+    mut tunnel: ngrok::tunnel::HttpTunnel,
+    forward_to: ListenAddress,
+    shutdown_rx: oneshot::Receiver<()>,
+) {
+    info!("Ngrok forwarding to: {}", forward_to);
+    let forwarding = async {
+        match forward_to {
+            ListenAddress::Tcp(addr) => tunnel.forward_tcp(addr).await,
+            ListenAddress::Unix(path) => tunnel.forward_pipe(path).await,
+        }
+    };
+
+    tokio::select! {
+        result = forwarding => match result {
+            Ok(()) => warn!("Ngrok tunnel closed"),
+            Err(e) => error!("Ngrok tunnel failed: {}", e),
+        },
+        _ = shutdown_rx => {
+            info!("Received shutdown signal, closing ngrok tunnel");
+        }
+    }
+    if let Err(e) = session.close().await {
+        warn!("Failed to close ngrok session: {}", e);
+    }
+}
+
+/// No tunnel at all, the stream is served to the local network only.
+#[derive(Debug)]
+pub struct LanTunnel {
+    url: String,
+    running: Mutex<bool>,
+}
+
+impl LanTunnel {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            url: config.public_url(),
+            running: Mutex::new(false),
+        }
+    }
+}
+
+#[async_trait]
+impl TunnelProvider for LanTunnel {
+    fn name(&self) -> &'static str { "lan" }
+
+    async fn start(&self) -> Result<(), TunnelError> {
+        let mut running = self.running.lock();
+        if *running {
+            return Err(TunnelError::AlreadyRunning);
+        }
+        *running = true;
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), TunnelError> {
+        let mut running = self.running.lock();
+        if !*running {
+            return Err(TunnelError::NotRunning);
+        }
+        *running = false;
+        Ok(())
+    }
+
+    fn status(&self) -> TunnelStatus {
+        if *self.running.lock() {
+            TunnelStatus::Running
+        } else {
+            TunnelStatus::Stopped
+        }
+    }
+
+    fn url(&self) -> String { self.url.clone() }
+}
+
+/// Runs a user-supplied shell command, e.g. an SSH reverse tunnel, for as long as the tunnel is up.
+#[derive(Debug)]
+pub struct CommandTunnel {
+    command: String,
+    url: String,
+    /// Serializes concurrent `/getvideo` requests during the startup check.
+    starting: tokio::sync::Mutex<()>,
+    child: Mutex<Option<Child>>,
+}
+
+impl CommandTunnel {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            command: config.tunnel_command.clone(),
+            url: config.public_url(),
+            starting: tokio::sync::Mutex::new(()),
+            child: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl TunnelProvider for CommandTunnel {
+    fn name(&self) -> &'static str { "command" }
+
+    async fn start(&self) -> Result<(), TunnelError> {
+        let _starting = self.starting.lock().await;
+        if self.status() == TunnelStatus::Running {
+            return Err(TunnelError::AlreadyRunning);
+        }
+        info!("Starting tunnel command: {}", self.command);
+        // own process group, so stopping also reaches the processes the shell has started
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .process_group(0)
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| TunnelError::Failed(format!("can not run {:?}: {}", self.command, e)))?;
+        // e.g. ssh exits at once on an authentication failure
+        if let Ok(status) = tokio::time::timeout(COMMAND_STARTUP_CHECK, child.wait()).await {
+            let reason = match status {
+                Ok(status) => format!("tunnel command exited with {status}"),
+                Err(e) => format!("tunnel command failed: {e}"),
+            };
+            return Err(TunnelError::Failed(reason));
+        }
+        *self.child.lock() = Some(child);
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), TunnelError> {
+        let mut child = self.child.lock().take().ok_or(TunnelError::NotRunning)?;
+        if !matches!(child.try_wait(), Ok(None)) {
+            // the command has exited on its own
+            return Err(TunnelError::NotRunning);
+        }
+        let stop_error = |e: String| TunnelError::Failed(format!("can not stop the tunnel command: {e}"));
+        let group = child
+            .id()
+            .and_then(|pid| i32::try_from(pid).ok())
+            .map(Pid::from_raw)
+            .ok_or_else(|| stop_error("no process id".to_string()))?;
+        killpg(group, Signal::SIGTERM).map_err(|e| stop_error(e.to_string()))?;
+        if tokio::time::timeout(COMMAND_STOP_TIMEOUT, child.wait()).await.is_err() {
+            warn!("Tunnel command ignored SIGTERM, killing it");
+            killpg(group, Signal::SIGKILL).map_err(|e| stop_error(e.to_string()))?;
+            child.wait().await.map_err(|e| stop_error(e.to_string()))?;
+        }
+        info!("Tunnel command stopped");
+        Ok(())
+    }
+
+    fn status(&self) -> TunnelStatus {
+        let mut child = self.child.lock();
+        match child.as_mut().map(Child::try_wait) {
+            Some(Ok(None)) => TunnelStatus::Running,
+            _ => TunnelStatus::Stopped,
+        }
+    }
+
+    fn url(&self) -> String { self.url.clone() }
+}
@@ -0,0 +1,199 @@
+use crate::config::{Role, TelegramConfig};
+use crate::core::write_file_atomic;
+use roboplc::locking::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// A user changed by the admin at runtime, `role` is not set for removed users.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredUser {
+    pub id: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UsersFile {
+    #[serde(default, rename = "user")]
+    users: Vec<StoredUser>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserSource {
+    Config,
+    Store,
+}
+
+/// A user with a role, as listed by `/users`.
+#[derive(Debug, Clone)]
+pub struct UserEntry {
+    pub id: i64,
+    pub role: Role,
+    pub name: String,
+    pub source: UserSource,
+}
+
+#[derive(Debug)]
+pub enum UserError {
+    /// The admin from the settings can not be changed at runtime.
+    ConfiguredAdmin,
+    Save(String),
+}
+
+impl fmt::Display for UserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UserError::ConfiguredAdmin => write!(f, "the admin from the settings can not be changed"),
+            UserError::Save(e) => write!(f, "failed to save users: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for UserError {}
+
+/// Bot users: the settings merged with the changes made by the admin, which are stored on disk.
+///
+/// Stored entries take precedence, so a user from the settings can be removed or get another role.
+#[derive(Debug)]
+pub struct UserStore {
+    path: PathBuf,
+    admin_user_id: i64,
+    configured: HashMap<i64, Role>,
+    file: Mutex<UsersFile>,
+    /// Pending access requests of unknown users, by user id.
+    requests: Mutex<HashMap<i64, String>>,
+}
+
+impl UserStore {
+    /// Loads the stored users from `path`, a missing or broken file leaves the settings only.
+    pub fn open(path: PathBuf, telegram: &TelegramConfig) -> Self {
+        let file = match Self::load(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Failed to read users from {}: {}", path.display(), e);
+                UsersFile::default()
+            }
+        };
+        let configured = telegram
+            .allowed_user_ids
+            .iter()
+            .chain(&telegram.operator_user_ids)
+            .filter(|&&id| id != telegram.admin_user_id)
+            .filter_map(|&id| telegram.role_of(id).map(|role| (id, role)))
+            .collect();
+        Self {
+            path,
+            admin_user_id: telegram.admin_user_id,
+            configured,
+            file: Mutex::new(file),
+            requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn load(path: &Path) -> Result<UsersFile, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(UsersFile::default());
+        }
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    fn save(&self, file: &UsersFile) -> Result<(), UserError> {
+        let content = toml::to_string(file).map_err(|e| UserError::Save(e.to_string()))?;
+        write_file_atomic(&self.path, &content).map_err(|e| UserError::Save(e.to_string()))
+    }
+
+    pub fn role_of(&self, user_id: i64) -> Option<Role> {
+        if user_id == self.admin_user_id {
+            return Some(Role::Admin);
+        }
+        let file = self.file.lock();
+        match file.users.iter().find(|user| user.id == user_id) {
+            Some(user) => user.role,
+            None => self.configured.get(&user_id).copied(),
+        }
+    }
+
+    /// Adds a user or changes the role of an existing one.
+    pub fn set_role(&self, user_id: i64, role: Role, name: &str) -> Result<(), UserError> {
+        self.update(user_id, Some(role), name)?;
+        info!(user_id, %role, "User role set");
+        Ok(())
+    }
+
+    /// Removes a user, returns `false` if the user had no role.
+    pub fn remove(&self, user_id: i64) -> Result<bool, UserError> {
+        let had_role = self.role_of(user_id).is_some();
+        self.update(user_id, None, "")?;
+        info!(user_id, "User removed");
+        Ok(had_role)
+    }
+
+    fn update(&self, user_id: i64, role: Option<Role>, name: &str) -> Result<(), UserError> {
+        if user_id == self.admin_user_id {
+            return Err(UserError::ConfiguredAdmin);
+        }
+        let mut file = self.file.lock();
+        match file.users.iter_mut().find(|user| user.id == user_id) {
+            Some(user) => {
+                user.role = role;
+                if !name.is_empty() {
+                    user.name = name.to_string();
+                }
+            }
+            None => file.users.push(StoredUser {
+                id: user_id,
+                role,
+                name: name.to_string(),
+            }),
+        }
+        self.save(&file)
+    }
+
+    /// All the users with a role, the highest roles first.
+    pub fn list(&self) -> Vec<UserEntry> {
+        let file = self.file.lock();
+        let mut users: Vec<UserEntry> = file
+            .users
+            .iter()
+            .filter_map(|user| {
+                user.role.map(|role| UserEntry {
+                    id: user.id,
+                    role,
+                    name: user.name.clone(),
+                    source: UserSource::Store,
+                })
+            })
+            .collect();
+        for (&id, &role) in &self.configured {
+            if !file.users.iter().any(|user| user.id == id) {
+                users.push(UserEntry {
+                    id,
+                    role,
+                    name: String::new(),
+                    source: UserSource::Config,
+                });
+            }
+        }
+        users.push(UserEntry {
+            id: self.admin_user_id,
+            role: Role::Admin,
+            name: String::new(),
+            source: UserSource::Config,
+        });
+        users.sort_by(|a, b| b.role.cmp(&a.role).then(a.id.cmp(&b.id)));
+        users
+    }
+
+    /// Records an access request, returns `false` if the user has asked already.
+    pub fn add_request(&self, user_id: i64, name: &str) -> bool {
+        self.requests.lock().insert(user_id, name.to_string()).is_none()
+    }
+
+    /// Removes a pending access request and returns the name of the user.
+    pub fn take_request(&self, user_id: i64) -> Option<String> { self.requests.lock().remove(&user_id) }
+}
@@ -1,34 +1,116 @@
 use crate::prelude::*;
+use crate::status::CameraState;
+use jpeg_encoder::{ColorType, Encoder};
+use roboplc::controller::WorkerOptions;
 use roboplc::prelude::*;
 use roboplc::rvideo;
-use roboplc_derive::WorkerOpts;
+use roboplc::thread_rt::Scheduling;
 use rscam::{Camera, Config};
 use serde::de::StdError;
-use std::time::Instant;
-use tracing::{debug, info};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
 
-#[derive(WorkerOpts)]
-#[worker_opts(cpu = 3, priority = 80, scheduling = "fifo", blocking = true)]
-pub struct DetectorVideo {
-    stream: Option<rvideo::Stream>,
+pub type SourceResult<T> = Result<T, Box<dyn StdError + Send + Sync + 'static>>;
+
+const TEST_PATTERN_QUALITY: u8 = 80;
+const TEST_PATTERN_BARS: [[u8; 3]; 8] = [
+    [255, 255, 255],
+    [255, 255, 0],
+    [0, 255, 255],
+    [0, 255, 0],
+    [255, 0, 255],
+    [255, 0, 0],
+    [0, 0, 255],
+    [0, 0, 0],
+];
+
+/// A source of JPEG frames consumed by [`DetectorVideo`].
+pub trait FrameSource: Send {
+    /// Short description of the source, used in logs.
+    fn describe(&self) -> String;
+
+    /// Opens the underlying device or data and prepares it for capturing.
+    fn start(&mut self) -> SourceResult<()>;
+
+    /// Blocks until the next JPEG frame is available and returns it.
+    fn capture(&mut self) -> SourceResult<Vec<u8>>;
+
+    /// Format of the frames after a successful start, like `MJPG 640x480 30 fps`.
+    fn format(&self) -> String;
 }
 
-impl DetectorVideo {
-    pub fn new_with_rvideo(stream: rvideo::Stream) -> Self { Self { stream: Some(stream) } }
+/// Creates the frame source selected by [`CameraConfig::source`].
+pub fn open_frame_source(config: &CameraConfig) -> Box<dyn FrameSource> {
+    match config.source {
+        CameraSource::V4l2 => Box::new(V4l2Source::new(config.clone())),
+        CameraSource::TestPattern => Box::new(TestPatternSource::new(config.clone())),
+        CameraSource::Replay(ref path) => Box::new(ReplaySource::new(path.clone(), config.interval)),
+    }
+}
 
-    pub fn new() -> Self { Self { stream: None } }
+/// Frame rate of a frame interval like `[1, 30]`.
+fn format_frame_rate(interval: (u32, u32)) -> String {
+    let (num, den) = interval;
+    if num == 0 || den == 0 {
+        return "unlimited fps".to_string();
+    }
+    format!("{} fps", f64::from(den) / f64::from(num))
 }
-impl Worker<WorkerMessage, Variables> for DetectorVideo {
-    fn run(&mut self, context: &Context<WorkerMessage, Variables>) -> Result<(), Box<(dyn StdError + Send + Sync + 'static)>> {
-        let variables = &context.variables().camera_config;
-        let dev_idx = variables.dev_idx.to_string();
+
+/// Keeps synthetic sources at the configured camera frame interval.
+struct FramePacer {
+    period: Duration,
+    next: Option<Instant>,
+}
+
+impl FramePacer {
+    fn new(interval: (u32, u32)) -> Self {
+        let (num, den) = interval;
+        let period = if den == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(f64::from(num) / f64::from(den))
+        };
+        Self { period, next: None }
+    }
+
+    fn wait(&mut self) {
+        let now = Instant::now();
+        let next = self.next.unwrap_or(now);
+        if next > now {
+            std::thread::sleep(next - now);
+        }
+        // do not try to catch up after a long stall
+        self.next = Some(next.max(now) + self.period);
+    }
+}
+
+/// V4L2 camera device (`/dev/video{dev_idx}`) captured through rscam.
+pub struct V4l2Source {
+    config: CameraConfig,
+    camera: Option<Camera>,
+}
+
+impl V4l2Source {
+    pub fn new(config: CameraConfig) -> Self { Self { config, camera: None } }
+
+    fn device_path(&self) -> String { format!("/dev/video{}", self.config.dev_idx) }
+}
+
+impl FrameSource for V4l2Source {
+    fn describe(&self) -> String { format!("v4l2 {}", self.device_path()) }
+
+    fn start(&mut self) -> SourceResult<()> {
+        let dev_idx = self.config.dev_idx.to_string();
         info!(dev_idx, "Opening camera device");
-        let mut camera = Camera::new(("/dev/video".to_string() + &dev_idx).as_str())?;
+        let mut camera = Camera::new(self.device_path().as_str())?;
         let config = Config {
-            interval: variables.interval,
-            resolution: (variables.width, variables.height),
-            format: &variables.fourcc,
-            nbuffers: variables.buf_size,
+            interval: self.config.interval,
+            resolution: (self.config.width, self.config.height),
+            format: &self.config.fourcc,
+            nbuffers: self.config.buf_size,
             ..Default::default()
         };
 
@@ -37,7 +119,7 @@ impl Worker<WorkerMessage, Variables> for DetectorVideo {
 
         // @todo add validation for the format
         for control in camera.formats() {
-            let format = control.unwrap();
+            let format = control?;
 
             info!("format: {:?}", format);
             camera.resolutions(&format.format).iter().for_each(|control| {
@@ -45,31 +127,320 @@ impl Worker<WorkerMessage, Variables> for DetectorVideo {
             });
         }
 
+        self.camera = Some(camera);
+        Ok(())
+    }
+
+    fn capture(&mut self) -> SourceResult<Vec<u8>> {
+        let camera = self.camera.as_ref().ok_or("camera is not started")?;
+        let frame = camera.capture()?;
+        Ok(frame.to_vec())
+    }
+
+    // rscam fails to start if the driver does not accept the requested format
+    fn format(&self) -> String {
+        format!(
+            "{} {}x{} {}",
+            String::from_utf8_lossy(&self.config.fourcc),
+            self.config.width,
+            self.config.height,
+            format_frame_rate(self.config.interval)
+        )
+    }
+}
+
+/// Synthetic moving color bars, encoded to JPEG. Useful without a webcam.
+pub struct TestPatternSource {
+    width: u16,
+    height: u16,
+    frame_no: usize,
+    interval: (u32, u32),
+    pacer: FramePacer,
+}
+
+impl TestPatternSource {
+    pub fn new(config: CameraConfig) -> Self {
+        Self {
+            width: u16::try_from(config.width).unwrap_or(u16::MAX),
+            height: u16::try_from(config.height).unwrap_or(u16::MAX),
+            frame_no: 0,
+            interval: config.interval,
+            pacer: FramePacer::new(config.interval),
+        }
+    }
+
+    fn render(&self) -> Vec<u8> {
+        let width = usize::from(self.width);
+        let height = usize::from(self.height);
+        let bar_width = (width / TEST_PATTERN_BARS.len()).max(1);
+        let box_size = (height / 6).max(1);
+        let box_x = (self.frame_no * 4) % width.max(1);
+        let box_y = height / 2 - box_size / 2;
+
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0 .. height {
+            for x in 0 .. width {
+                let in_box = (box_x .. box_x + box_size).contains(&x) && (box_y .. box_y + box_size).contains(&y);
+                let color = if in_box {
+                    [0x80, 0x80, 0x80]
+                } else {
+                    let bar = ((x + self.frame_no) / bar_width) % TEST_PATTERN_BARS.len();
+                    TEST_PATTERN_BARS[bar]
+                };
+                pixels.extend_from_slice(&color);
+            }
+        }
+        pixels
+    }
+}
+
+impl FrameSource for TestPatternSource {
+    fn describe(&self) -> String { format!("test pattern {}x{}", self.width, self.height) }
+
+    fn start(&mut self) -> SourceResult<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err("test pattern resolution must not be zero".into());
+        }
+        Ok(())
+    }
+
+    fn capture(&mut self) -> SourceResult<Vec<u8>> {
+        self.pacer.wait();
+        let pixels = self.render();
+        let mut jpeg = Vec::new();
+        Encoder::new(&mut jpeg, TEST_PATTERN_QUALITY).encode(&pixels, self.width, self.height, ColorType::Rgb)?;
+        self.frame_no = self.frame_no.wrapping_add(1);
+        Ok(jpeg)
+    }
+
+    fn format(&self) -> String { format!("JPEG {}x{} {}", self.width, self.height, format_frame_rate(self.interval)) }
+}
+
+/// Replays recorded JPEG frames in a loop.
+///
+/// The path may point to a directory of `.jpg`/`.jpeg` files (played in name order),
+/// a single JPEG file, or a raw MJPEG file made of concatenated JPEG images.
+pub struct ReplaySource {
+    path: PathBuf,
+    frames: ReplayFrames,
+    position: usize,
+    interval: (u32, u32),
+    pacer: FramePacer,
+}
+
+enum ReplayFrames {
+    Files(Vec<PathBuf>),
+    Memory(Vec<Vec<u8>>),
+}
+
+impl ReplayFrames {
+    fn len(&self) -> usize {
+        match self {
+            ReplayFrames::Files(files) => files.len(),
+            ReplayFrames::Memory(frames) => frames.len(),
+        }
+    }
+}
+
+impl ReplaySource {
+    pub fn new(path: PathBuf, interval: (u32, u32)) -> Self {
+        Self {
+            path,
+            frames: ReplayFrames::Memory(Vec::new()),
+            position: 0,
+            interval,
+            pacer: FramePacer::new(interval),
+        }
+    }
+
+    fn load_dir(path: &Path) -> SourceResult<ReplayFrames> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(path)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| is_jpeg_file(path))
+            .collect();
+        files.sort();
+        Ok(ReplayFrames::Files(files))
+    }
+
+    fn load_file(path: &Path) -> SourceResult<ReplayFrames> {
+        let data = std::fs::read(path)?;
+        Ok(ReplayFrames::Memory(split_jpeg_frames(&data)))
+    }
+}
+
+impl FrameSource for ReplaySource {
+    fn describe(&self) -> String { format!("replay {}", self.path.display()) }
+
+    fn start(&mut self) -> SourceResult<()> {
+        self.frames = if self.path.is_dir() {
+            Self::load_dir(&self.path)?
+        } else {
+            Self::load_file(&self.path)?
+        };
+        if self.frames.len() == 0 {
+            return Err(format!("no JPEG frames found in {}", self.path.display()).into());
+        }
+        info!(path = %self.path.display(), frames = self.frames.len(), "Replay source loaded");
+        Ok(())
+    }
+
+    fn capture(&mut self) -> SourceResult<Vec<u8>> {
+        self.pacer.wait();
+        let frame = match self.frames {
+            ReplayFrames::Files(ref files) => std::fs::read(&files[self.position])?,
+            ReplayFrames::Memory(ref frames) => frames[self.position].clone(),
+        };
+        self.position = (self.position + 1) % self.frames.len();
+        Ok(frame)
+    }
+
+    fn format(&self) -> String { format!("JPEG {} frames {}", self.frames.len(), format_frame_rate(self.interval)) }
+}
+
+fn is_jpeg_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg"))
+}
+
+/// Splits a buffer of concatenated JPEG images on SOI/EOI markers.
+fn split_jpeg_frames(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+    while i + 1 < data.len() {
+        match (data[i], data[i + 1]) {
+            (0xFF, 0xD8) if start.is_none() => {
+                start = Some(i);
+                i += 2;
+            }
+            (0xFF, 0xD9) if start.is_some() => {
+                if let Some(begin) = start.take() {
+                    frames.push(data[begin .. i + 2].to_vec());
+                }
+                i += 2;
+            }
+            _ => i += 1,
+        }
+    }
+    if start.is_some() {
+        warn!("Trailing incomplete JPEG frame ignored");
+    }
+    frames
+}
+
+const DETECTOR_VIDEO_CPU: [usize; 1] = [3];
+/// Period the current frame rate of [`CameraStats`] is measured over.
+///
+/// [`CameraStats`]: crate::status::CameraStats
+const FPS_WINDOW: Duration = Duration::from_secs(2);
+
+/// Captures frames of a single camera and publishes them to the hub and the rvideo stream.
+pub struct DetectorVideo {
+    camera: CameraConfig,
+    worker_name: String,
+    stream: Option<rvideo::Stream>,
+}
+
+impl DetectorVideo {
+    pub fn new_with_rvideo(camera: CameraConfig, stream: rvideo::Stream) -> Self {
+        Self {
+            stream: Some(stream),
+            ..Self::new(camera)
+        }
+    }
+
+    pub fn new(camera: CameraConfig) -> Self {
+        Self {
+            // thread names are limited to 15 characters
+            worker_name: format!("cam.{}", camera.name).chars().take(15).collect(),
+            camera,
+            stream: None,
+        }
+    }
+}
+
+impl WorkerOptions for DetectorVideo {
+    fn worker_name(&self) -> &str { &self.worker_name }
+
+    fn worker_scheduling(&self) -> Scheduling { Scheduling::FIFO }
+
+    fn worker_priority(&self) -> Option<i32> { Some(80) }
+
+    fn worker_cpu_ids(&self) -> Option<&[usize]> { Some(&DETECTOR_VIDEO_CPU) }
+
+    fn worker_is_blocking(&self) -> bool { true }
+}
+
+impl Worker<WorkerMessage, Variables> for DetectorVideo {
+    fn run(&mut self, context: &Context<WorkerMessage, Variables>) -> WResult {
+        let stats = &context.variables().camera_stats;
+        let name = &self.camera.name;
+        let failed = |e: &(dyn StdError + Send + Sync)| {
+            stats.update(name, |stats| {
+                stats.state = CameraState::Failed;
+                stats.error = Some(e.to_string());
+            });
+        };
+
+        let mut source = open_frame_source(&self.camera);
+        info!(camera = self.camera.name, source = source.describe(), "Starting frame source");
+        stats.update(name, |stats| stats.source = source.describe());
+        source.start().inspect_err(|e| failed(&**e))?;
+        stats.update(name, |stats| {
+            stats.state = CameraState::Running;
+            stats.format = source.format();
+        });
+
         let start_time = Instant::now();
         let mut frame_count = 0;
         let mut total_bytes = 0;
+        let mut window_start = start_time;
+        let mut window_frames = 0;
 
         loop {
-            let frame = camera.capture()?;
+            let frame_data = source.capture().inspect_err(|e| failed(&**e))?;
             frame_count += 1;
-            total_bytes += frame.len();
+            total_bytes += frame_data.len();
+            window_frames += 1;
 
-
-            let frame_data = frame.to_vec();
+            let window = window_start.elapsed();
+            let fps = (window >= FPS_WINDOW).then(|| f64::from(window_frames) / window.as_secs_f64());
+            if fps.is_some() {
+                window_start = Instant::now();
+                window_frames = 0;
+            }
+            stats.update(name, |stats| {
+                stats.frames += 1;
+                stats.bytes += frame_data.len() as u64;
+                if let Some(fps) = fps {
+                    stats.fps = fps;
+                }
+            });
 
             if let Some(ref mut stream) = self.stream {
                 stream.send_frame(rvideo::Frame::from(frame_data.clone()))?;
             }
 
-            context.hub().send(WorkerMessage::Frame(frame_data.clone()));
+            context.hub().send(WorkerMessage::Frame(CameraFrame {
+                camera: self.camera.name.clone(),
+                data: frame_data.clone(),
+            }));
+            context
+                .variables()
+                .frame_cache
+                .update(&self.camera.name, Arc::new(frame_data));
 
             if frame_count % 120 == 0 {
                 let elapsed = start_time.elapsed();
                 let mb_processed = total_bytes as f64 / (1024.0 * 1024.0);
-                let average_fps = frame_count as f64 / elapsed.as_secs_f64();
-                debug!("Average FPS: {:.2}", average_fps);
-                debug!("Elapsed: {:.2}", elapsed.as_secs_f64());
-                debug!("MB processed: {:.2}", mb_processed);
+                let average_fps = f64::from(frame_count) / elapsed.as_secs_f64();
+                debug!(camera = self.camera.name, "Average FPS: {:.2}", average_fps);
+                debug!(camera = self.camera.name, "Elapsed: {:.2}", elapsed.as_secs_f64());
+                debug!(camera = self.camera.name, "MB processed: {:.2}", mb_processed);
             }
         }
     }
@@ -1,9 +1,15 @@
 pub mod camera;
+pub mod motion;
+pub mod preroll;
+pub mod recorder;
 pub mod rvideo;
 pub mod telegram_bot;
 pub mod ws_server;
 
 pub use camera::*;
+pub use motion::*;
+pub use preroll::*;
+pub use recorder::*;
 pub use rvideo::*;
 pub use telegram_bot::*;
 pub use ws_server::*;
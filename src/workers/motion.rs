@@ -0,0 +1,103 @@
+use crate::core::{CameraEvent, EventKind, Variables, WorkerMessage};
+use crate::motion::{LumaFrame, MotionAnalyzer};
+use roboplc::controller::{Context, WResult, Worker, WorkerOptions};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// Detects motion in the frames of a camera from the hub while [`Variables::motion`] is armed
+/// and sends a [`EventKind::Motion`] event to the hub, which the bot turns into alerts.
+///
+/// The frames are compared downscaled and in grayscale, at most
+/// [`MotionConfig::max_fps`](crate::config::MotionConfig::max_fps) of them per second.
+pub struct MotionDetector {
+    camera: String,
+    worker_name: String,
+}
+
+impl MotionDetector {
+    pub fn new(camera: &str) -> Self {
+        Self {
+            // thread names are limited to 15 characters
+            worker_name: format!("motion.{camera}").chars().take(15).collect(),
+            camera: camera.to_string(),
+        }
+    }
+}
+
+impl WorkerOptions for MotionDetector {
+    fn worker_name(&self) -> &str { &self.worker_name }
+
+    fn worker_is_blocking(&self) -> bool { true }
+}
+
+impl Worker<WorkerMessage, Variables> for MotionDetector {
+    fn run(&mut self, context: &Context<WorkerMessage, Variables>) -> WResult {
+        let variables = context.variables();
+        let config = &variables.config.motion;
+        let camera = self.camera.clone();
+        let client = context
+            .hub()
+            .register(&format!("motion: {}", self.camera), move |msg: &WorkerMessage| {
+                msg.is_frame_of(&camera)
+            })?;
+        let min_interval = Duration::from_secs(1) / config.max_fps;
+        let zones = variables
+            .config
+            .camera(&self.camera)
+            .map(|camera| camera.zones.clone())
+            .unwrap_or_default();
+        let mut analyzer = MotionAnalyzer::new(config.sensitivity, zones);
+        let mut last_analyzed: Option<Instant> = None;
+        let mut last_event: Option<Instant> = None;
+        info!(
+            camera = self.camera,
+            sensitivity = config.sensitivity,
+            min_area_percent = config.min_area_percent,
+            "Motion detection started"
+        );
+
+        while let Ok(msg) = client.recv() {
+            let frame = match msg {
+                WorkerMessage::Frame(frame) => frame,
+                WorkerMessage::Terminate => break,
+                WorkerMessage::Event(_) => continue,
+            };
+            if !variables.motion.is_armed() {
+                // the first frame after arming must not be compared with an old one
+                analyzer.reset();
+                continue;
+            }
+            let now = Instant::now();
+            if last_analyzed.is_some_and(|last| now.duration_since(last) < min_interval) {
+                continue;
+            }
+            last_analyzed = Some(now);
+            let luma = match LumaFrame::decode(&frame.data) {
+                Ok(luma) => luma,
+                Err(e) => {
+                    debug!(camera = self.camera, "Failed to decode frame: {}", e);
+                    continue;
+                }
+            };
+            let Some(area_percent) = analyzer.analyze(luma) else {
+                continue;
+            };
+            if area_percent < config.min_area_percent || last_event.is_some_and(|last| now.duration_since(last) < config.cooldown)
+            {
+                continue;
+            }
+            last_event = Some(now);
+            info!(
+                camera = self.camera,
+                "Motion detected, {:.1}% of the picture changed", area_percent
+            );
+            context.hub().send(WorkerMessage::Event(CameraEvent {
+                camera: self.camera.clone(),
+                kind: EventKind::Motion { area_percent },
+                frame: Some(Arc::new(frame.data)),
+            }));
+        }
+        Ok(())
+    }
+}
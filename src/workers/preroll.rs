@@ -0,0 +1,25 @@
+use crate::core::{Variables, WorkerMessage};
+use roboplc::controller::{Context, WResult, Worker};
+use roboplc_derive::WorkerOpts;
+use tracing::info;
+
+/// Keeps the frames of all cameras from the hub in [`Variables::preroll`].
+#[derive(WorkerOpts)]
+#[worker_opts(name = "preroll", blocking = true)]
+pub struct PrerollWorker {}
+
+impl Worker<WorkerMessage, Variables> for PrerollWorker {
+    fn run(&mut self, context: &Context<WorkerMessage, Variables>) -> WResult {
+        let preroll = &context.variables().preroll;
+        let client = context
+            .hub()
+            .register("preroll", |msg: &WorkerMessage| matches!(msg, WorkerMessage::Frame(_)))?;
+        info!("Keeping the last {:.0} s of frames", preroll.length().as_secs_f64());
+        while let Ok(msg) = client.recv() {
+            if let WorkerMessage::Frame(frame) = msg {
+                preroll.push(&frame.camera, frame.data);
+            }
+        }
+        Ok(())
+    }
+}
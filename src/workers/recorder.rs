@@ -0,0 +1,363 @@
+use crate::avi::{jpeg_dimensions, AviWriter, HEADER_SIZE};
+use crate::bandwidth::BYTES_PER_MB;
+use crate::config::RecordingMode;
+use crate::core::{utc_date, Variables, WorkerMessage};
+use crate::recording::{unix_millis, Segment};
+use roboplc::controller::{Context, WResult, Worker, WorkerOptions};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, warn};
+
+/// How often the hub queue is polled, also bounds the delay of closing a segment.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Segments are closed early at this size, AVI files are limited to 4 GB.
+const MAX_SEGMENT_BYTES: u64 = 1024 * 1024 * 1024;
+/// Extension of the segment being written, renamed to `.avi` when it is finished.
+const PART_EXTENSION: &str = "part";
+
+struct OpenSegment {
+    writer: AviWriter<BufWriter<File>>,
+    /// Path relative to the recording directory.
+    file: String,
+    start: SystemTime,
+    started: Instant,
+    bytes: u64,
+}
+
+/// Records the frames of a camera from the hub into MJPEG AVI segments and indexes them in
+/// [`Variables::recordings`].
+///
+/// In the [`RecordingMode::Events`] mode, only the frames around the events on the hub are
+/// written, starting with the frames kept in [`Variables::preroll`].
+pub struct RecorderWorker {
+    camera: String,
+    worker_name: String,
+}
+
+impl RecorderWorker {
+    pub fn new(camera: &str) -> Self {
+        Self {
+            // thread names are limited to 15 characters
+            worker_name: format!("rec.{camera}").chars().take(15).collect(),
+            camera: camera.to_string(),
+        }
+    }
+
+    /// Starts a segment with `frame`, which was received at `received`.
+    fn open_segment(&self, dir: &Path, frame: &[u8], received: Instant) -> io::Result<OpenSegment> {
+        let (width, height) =
+            jpeg_dimensions(frame).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a JPEG frame"))?;
+        let start = SystemTime::now() - received.elapsed();
+        let file = format!("{}/{}.avi", self.camera, file_time(start));
+        let part = dir.join(&file).with_extension(PART_EXTENSION);
+        // never replaces a segment of the same name, the next frame retries with a later time
+        if dir.join(&file).exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{file} exists")));
+        }
+        let out = OpenOptions::new().write(true).create_new(true).open(&part)?;
+        let writer = AviWriter::new(BufWriter::new(out), width, height)?;
+        Ok(OpenSegment {
+            writer,
+            file,
+            start,
+            started: received,
+            bytes: 0,
+        })
+    }
+
+    /// Writes a frame, starting a segment if none is open. A segment which fails is dropped,
+    /// the next frame starts a new one.
+    fn write_frame(&self, segment: &mut Option<OpenSegment>, dir: &Path, frame: &[u8], received: Instant) {
+        if segment.is_none() {
+            match self.open_segment(dir, frame, received) {
+                Ok(opened) => *segment = Some(opened),
+                Err(e) => {
+                    error!(camera = self.camera, "Failed to start segment: {}", e);
+                    return;
+                }
+            }
+        }
+        let Some(open) = segment.as_mut() else {
+            return;
+        };
+        if let Err(e) = open.writer.write_frame(frame) {
+            // e.g. a full disk
+            error!(camera = self.camera, "Failed to write segment {}: {}", open.file, e);
+            let part = dir.join(&open.file).with_extension(PART_EXTENSION);
+            *segment = None;
+            let _ = std::fs::remove_file(part);
+            return;
+        }
+        open.bytes += frame.len() as u64;
+    }
+
+    /// Rewrites the segments left unfinished by a power loss or a crash into playable files,
+    /// only their headers and index are missing.
+    fn recover_unfinished(&self, variables: &Variables, camera_dir: &Path) {
+        let Ok(entries) = std::fs::read_dir(camera_dir) else {
+            return;
+        };
+        for part in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+            if part.extension().is_none_or(|ext| ext != PART_EXTENSION) {
+                continue;
+            }
+            match self.recover(variables, &part) {
+                Ok(Some(segment)) => {
+                    info!(
+                        camera = self.camera,
+                        frames = segment.frames,
+                        "Unfinished segment {} recovered",
+                        segment.file
+                    );
+                    variables.recordings.add(segment);
+                }
+                Ok(None) => debug!(camera = self.camera, "Empty segment {} deleted", part.display()),
+                Err(e) => warn!(camera = self.camera, "Failed to recover segment {}: {}", part.display(), e),
+            }
+            let _ = std::fs::remove_file(&part);
+        }
+    }
+
+    fn recover(&self, variables: &Variables, part: &Path) -> io::Result<Option<Segment>> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let start = part
+            .file_stem()
+            .and_then(|stem| parse_file_time(&stem.to_string_lossy()))
+            .ok_or_else(|| invalid("unexpected file name"))?;
+        let metadata = std::fs::metadata(part)?;
+        let end = metadata.modified()?;
+        let part_len = metadata.len();
+        let path = part.with_extension("avi");
+
+        let mut reader = BufReader::new(File::open(part)?);
+        reader.seek(SeekFrom::Start(HEADER_SIZE))?;
+        let mut writer = None;
+        let mut head = [0; 8];
+        // stops at the first incomplete chunk
+        while reader.read_exact(&mut head).is_ok() && head.starts_with(b"00dc") {
+            let size = u64::from(u32::from_le_bytes([head[4], head[5], head[6], head[7]]));
+            // a torn or corrupt chunk header must not allocate more than the rest of the file
+            if size > part_len.saturating_sub(reader.stream_position()?) {
+                break;
+            }
+            let size = size as usize;
+            let mut frame = vec![0; size + size % 2];
+            if reader.read_exact(&mut frame).is_err() {
+                break;
+            }
+            frame.truncate(size);
+            if writer.is_none() {
+                let (width, height) = jpeg_dimensions(&frame).ok_or_else(|| invalid("not a JPEG frame"))?;
+                writer = Some(AviWriter::new(BufWriter::new(File::create(&path)?), width, height)?);
+            }
+            if let Some(ref mut writer) = writer {
+                writer.write_frame(&frame)?;
+            }
+        }
+        let Some(writer) = writer else {
+            return Ok(None);
+        };
+        let frames = writer.frames();
+        let duration = end.duration_since(start).unwrap_or_default();
+        writer.finish(duration)?;
+        let start_ms = unix_millis(start);
+        Ok(Some(Segment {
+            camera: self.camera.clone(),
+            file: path
+                .strip_prefix(variables.recordings.dir())
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .into_owned(),
+            start_ms,
+            end_ms: start_ms + duration.as_millis() as u64,
+            frames,
+            bytes: std::fs::metadata(&path)?.len(),
+        }))
+    }
+
+    /// Finishes the file and adds it to the index.
+    fn close_segment(&self, variables: &Variables, mut segment: OpenSegment) -> io::Result<()> {
+        let dir = variables.recordings.dir();
+        let duration = segment.started.elapsed();
+        let frames = segment.writer.frames();
+        segment
+            .writer
+            .finish(duration)?
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .sync_all()?;
+        let path = dir.join(&segment.file);
+        std::fs::rename(path.with_extension(PART_EXTENSION), &path)?;
+        segment.bytes = std::fs::metadata(&path)?.len();
+        info!(camera = self.camera, frames, "Segment {} finished", segment.file);
+
+        let start_ms = unix_millis(segment.start);
+        variables.recordings.add(Segment {
+            camera: self.camera.clone(),
+            file: segment.file,
+            start_ms,
+            end_ms: start_ms + duration.as_millis() as u64,
+            frames,
+            bytes: segment.bytes,
+        });
+        let config = &variables.config.recording;
+        variables.recordings.enforce_retention(
+            Duration::from_secs(config.max_age_hours * 3600),
+            config.max_size_mb * BYTES_PER_MB,
+        );
+        Ok(())
+    }
+}
+
+impl WorkerOptions for RecorderWorker {
+    fn worker_name(&self) -> &str { &self.worker_name }
+
+    fn worker_is_blocking(&self) -> bool { true }
+}
+
+impl Worker<WorkerMessage, Variables> for RecorderWorker {
+    fn run(&mut self, context: &Context<WorkerMessage, Variables>) -> WResult {
+        let variables = context.variables();
+        let config = &variables.config.recording;
+        let dir = variables.recordings.dir().to_path_buf();
+        let camera_dir = dir.join(&self.camera);
+        std::fs::create_dir_all(&camera_dir)?;
+        self.recover_unfinished(variables, &camera_dir);
+
+        let camera = self.camera.clone();
+        let client = context
+            .hub()
+            .register(&format!("recorder: {}", self.camera), move |msg: &WorkerMessage| {
+                msg.is_frame_of(&camera) || msg.is_event_of(&camera)
+            })?;
+        let min_interval = (config.max_fps > 0).then(|| Duration::from_secs(1) / config.max_fps);
+        let too_early = |last: Option<Instant>, received: Instant| {
+            min_interval.is_some_and(|interval| last.is_some_and(|last| received.duration_since(last) < interval))
+        };
+        let events = config.mode == RecordingMode::Events;
+        info!(camera = self.camera, dir = %dir.display(), mode = %config.mode, "Recording started");
+
+        let mut segment: Option<OpenSegment> = None;
+        let mut last_frame: Option<Instant> = None;
+        // in the `events` mode, frames are written until then
+        let mut active_until: Option<Instant> = None;
+        // the newest pre-roll frame may also be waiting in the queue of the client
+        let mut last_preroll: Option<Arc<Vec<u8>>> = None;
+        while context.is_online() {
+            let now = Instant::now();
+            let active = !events || active_until.is_some_and(|until| now < until);
+            // closed on time even if the camera has stopped sending frames
+            if segment
+                .as_ref()
+                .is_some_and(|s| !active || s.started.elapsed() >= config.segment_length || s.bytes >= MAX_SEGMENT_BYTES)
+            {
+                if let Some(segment) = segment.take() {
+                    if let Err(e) = self.close_segment(variables, segment) {
+                        error!(camera = self.camera, "Failed to finish segment: {}", e);
+                    }
+                }
+            }
+            let frame = match client.try_recv() {
+                Ok(WorkerMessage::Frame(frame)) => frame,
+                Ok(WorkerMessage::Event(event)) => {
+                    if events {
+                        info!(camera = self.camera, kind = %event.kind, "Event, recording");
+                        active_until = Some(now + config.post_event);
+                        if segment.is_none() {
+                            for frame in variables.preroll.frames(&self.camera) {
+                                if !too_early(last_frame, frame.received) {
+                                    last_frame = Some(frame.received);
+                                    self.write_frame(&mut segment, &dir, &frame.data, frame.received);
+                                }
+                                last_preroll = Some(frame.data);
+                            }
+                        }
+                    }
+                    continue;
+                }
+                Ok(WorkerMessage::Terminate) => break,
+                Err(_) => {
+                    sleep(POLL_INTERVAL);
+                    continue;
+                }
+            };
+            if !active || last_preroll.take().is_some_and(|last| *last == frame.data) {
+                continue;
+            }
+            if too_early(last_frame, now) {
+                continue;
+            }
+            last_frame = Some(now);
+            self.write_frame(&mut segment, &dir, &frame.data, now);
+        }
+
+        if let Some(segment) = segment {
+            self.close_segment(variables, segment)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the start time of a segment from its file name, see [`file_time`].
+///
+/// Names without milliseconds, like `20240915-120000`, are written by older versions.
+fn parse_file_time(stem: &str) -> Option<SystemTime> {
+    let number = |range: std::ops::Range<usize>| stem.get(range)?.parse::<u64>().ok();
+    let millis = match stem.len() {
+        15 => 0,
+        19 if stem.as_bytes()[15] == b'-' => number(16 .. 19)?,
+        _ => return None,
+    };
+    if stem.as_bytes()[8] != b'-' {
+        return None;
+    }
+    let (year, month, day) = (number(0 .. 4)?, number(4 .. 6)?, number(6 .. 8)?);
+    let secs = number(9 .. 11)? * 3600 + number(11 .. 13)? * 60 + number(13 .. 15)?;
+    // days from civil, http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year.checked_sub(1)? } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146_097 + doe).checked_sub(719_468)?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86_400 + secs) + Duration::from_millis(millis))
+}
+
+/// Start time (UTC) of a segment for its file name, like `20240915-120000-250`.
+///
+/// The milliseconds keep apart the segments started within the same second.
+fn file_time(time: SystemTime) -> String {
+    let (year, month, day) = utc_date(time);
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() % 86_400;
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}-{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_time_round_trip() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_726_401_600_250);
+        assert_eq!(file_time(time), "20240915-120000-250");
+        assert_eq!(parse_file_time(&file_time(time)), Some(time));
+        assert_eq!(
+            parse_file_time("20240915-120000"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_726_401_600))
+        );
+        assert_eq!(parse_file_time("20240915-120000.250"), None);
+        assert_eq!(parse_file_time("segment"), None);
+    }
+}
@@ -1,25 +1,43 @@
-use crate::core::{Variables, WorkerMessage};
-use ngrok::config::TunnelBuilder;
-use ngrok::prelude::{TunnelExt, UrlTunnel};
+use crate::bandwidth::{format_mb, BYTES_PER_MB};
+use crate::clip::{self, Clip};
+use crate::config::{MotionZone, NotifyEvent, QuietHours, Role, MAX_BURST_INTERVAL};
+use crate::core::{CameraEvent, CapturedFrame, EventKind, FrameError, Variables, WorkerMessage};
+use crate::motion;
+use crate::status::StatusReport;
+use crate::tunnel::{TunnelError, TunnelStatus};
+use crate::users::{UserError, UserSource};
 use reqwest::blocking::Client;
 use roboplc::controller::{Context, WResult, Worker};
-use roboplc::event_matches;
 use roboplc_derive::WorkerOpts;
-use std::fmt::Debug;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use teloxide::dispatching::{Dispatcher, HandlerExt, UpdateFilterExt};
 use teloxide::macros::BotCommands;
+use teloxide::payloads::{
+    AnswerCallbackQuerySetters, EditMessageTextSetters, SendDocumentSetters, SendMessageSetters, SendPhotoSetters,
+};
 use teloxide::prelude::{ChatId, Message, Request, Requester, ResponseResult, Update};
-use teloxide::types::InputFile;
+use teloxide::types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, InputMedia, InputMediaPhoto, User};
 use teloxide::utils::command::BotCommands as UtilsBotCommands;
 use teloxide::{dptree, Bot};
-use tokio::net::ToSocketAddrs;
 use tokio::runtime::Runtime;
-use tokio::sync::oneshot;
+use tokio::time::Instant;
 use tracing::{debug, error, info, warn};
 
+/// How often the bandwidth totals are saved and checked against the budget.
+const BANDWIDTH_WATCH_INTERVAL: Duration = Duration::from_secs(30);
+/// How often a running tunnel is checked for viewers and its session length.
+const TUNNEL_WATCH_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the held admin notifications are checked for a digest.
+const NOTIFY_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_BURST_FRAMES: u64 = 5;
+const DEFAULT_BURST_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_CLIP_LENGTH: Duration = Duration::from_secs(10);
+/// Callback data prefix of the `/menu` panel buttons.
+const MENU_CALLBACK_PREFIX: &str = "menu:";
+
 #[derive(WorkerOpts)]
 #[worker_opts(cpu = 2, priority = 80, scheduling = "fifo", blocking = true)]
 pub struct BotWorker {}
@@ -33,8 +51,8 @@ impl Worker<WorkerMessage, Variables> for BotWorker {
         }
 
         info!("Internet connection established. Starting bot...");
-        let result = Runtime::new().unwrap().block_on(bot(context));
-        Ok(result)
+        Runtime::new().unwrap().block_on(bot(context));
+        Ok(())
     }
 }
 
@@ -58,61 +76,91 @@ fn check_internet_connection() -> bool {
 }
 
 
-pub fn run_ngrok(
-    auth_token: String,
-    domain: String,
-    forward_to: impl ToSocketAddrs + Send + Debug + 'static,
-) -> oneshot::Sender<()> {
-    let (shutdown_tx, shutdown_rx) = oneshot::channel();
-
-    tokio::spawn(async move {
-        listen_ngrok(auth_token, domain, forward_to, shutdown_rx).await;
-    });
-
-    shutdown_tx
-}
-
-async fn listen_ngrok(
-    auth_token: impl Into<String>,
-    domain: impl Into<String>,
-    forward_to: impl ToSocketAddrs + Send + Debug,
-    shutdown_rx: oneshot::Receiver<()>,
-) {
-    let session = ngrok::Session::builder().authtoken(auth_token).connect().await.unwrap();
-    let mut tunnel = session.http_endpoint().compression().domain(domain).listen().await.unwrap();
-
-    info!("Ngrok trying to forward tcp");
-    let url = tunnel.url().to_string();
-    info!("Ngrok forwarding to: {:?}", forward_to);
-    let tunnel = tunnel.forward_tcp(forward_to);
-    info!("Ngrok tunnel established at: {}", url);
-
-    tokio::select! {
-        _ = tunnel => {
-            info!("Ngrok tunnel closed");
-        }
-        _ = shutdown_rx => {
-            info!("Received shutdown signal, closing ngrok tunnel");
-        }
-    }
-}
-
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase")]
 enum Command {
     #[command(description = "List commands.")]
     Help,
-    #[command(description = "Get a photo from the camera.")]
-    Photo,
+    #[command(description = "Get a photo from the camera: /photo [camera].")]
+    Photo(String),
+    #[command(description = "Get several frames as one album: /burst [camera] [count] [interval_ms].")]
+    Burst(String),
+    #[command(description = "Record a video clip: /clip [camera] [seconds].")]
+    Clip(String),
+    #[command(description = "Arm the motion detection.")]
+    Arm,
+    #[command(description = "Disarm the motion detection.")]
+    Disarm,
+    #[command(description = "Turn your motion alerts on or off: /alerts [on|off].")]
+    Alerts(String),
+    #[command(description = "Show the motion detection zones on a photo: /zones [camera].")]
+    Zones(String),
     #[command(description = "Get a URL with video stream.")]
     GetVideo,
     #[command(description = "Stop video stream.")]
     StopVideo,
+    #[command(description = "Show the camera, stream and system status.")]
+    Status,
+    #[command(description = "Show the control panel.")]
+    Menu,
+    #[command(description = "Show the current settings.")]
+    Settings,
+    #[command(description = "Ask the admin for access.")]
+    RequestAccess,
+    #[command(description = "Add a user: /adduser <id> [viewer|operator|admin].")]
+    AddUser(String),
+    #[command(description = "Remove a user: /removeuser <id>.")]
+    RemoveUser(String),
+    #[command(description = "List users and their roles.")]
+    Users,
+    #[command(description = "Change the role of a user: /setrole <id> <viewer|operator|admin>.")]
+    SetRole(String),
+    #[command(
+        description = "Show or change admin notifications: /notify [<event>|all|own on|off, quiet <HH:MM-HH:MM>|off, digest <secs>, reset]."
+    )]
+    Notify(String),
+}
+
+impl Command {
+    /// Permission matrix, the lowest role allowed to run the command or `None` if anyone may.
+    fn required_role(&self) -> Option<Role> {
+        match self {
+            Command::Help | Command::RequestAccess => None,
+            Command::Photo(_)
+            | Command::Burst(_)
+            | Command::Clip(_)
+            | Command::GetVideo
+            | Command::Status
+            | Command::Menu
+            | Command::Settings
+            | Command::Alerts(_)
+            | Command::Zones(_) => Some(Role::Viewer),
+            Command::StopVideo | Command::Arm | Command::Disarm => Some(Role::Operator),
+            Command::AddUser(_) | Command::RemoveUser(_) | Command::Users | Command::SetRole(_) | Command::Notify(_) => {
+                Some(Role::Admin)
+            }
+        }
+    }
+}
+
+/// Sender of a command and their role, resolved once per update.
+#[derive(Debug, Clone, Copy)]
+struct Caller {
+    user_id: i64,
+    role: Option<Role>,
+}
+
+impl Caller {
+    fn can_run(&self, command: &Command) -> bool {
+        command
+            .required_role()
+            .is_none_or(|required| self.role.is_some_and(|role| role >= required))
+    }
 }
 
 /// Bot initialization and command handling
 async fn bot(context: &Context<WorkerMessage, Variables>) {
-    let telegram_config = &context.variables().telegram_config;
+    let telegram_config = &context.variables().config.telegram_config;
     let bot = Bot::new(&telegram_config.token);
 
     for command in Command::bot_commands() {
@@ -123,18 +171,43 @@ async fn bot(context: &Context<WorkerMessage, Variables>) {
         .await
         .expect("Failed to set bot commands");
 
-    let handler = Update::filter_message()
-        .branch(dptree::entry().filter_command::<Command>().endpoint(command_handler))
+    let messages = Update::filter_message()
         .branch(
-            dptree::filter(|msg: Message| !msg.text().map_or(false, |text| text.starts_with('/')))
+            dptree::entry()
+                .filter_command::<Command>()
+                .map(resolve_caller)
+                .branch(dptree::filter(|cmd: Command, caller: Caller| caller.can_run(&cmd)).endpoint(command_handler))
+                .endpoint(permission_denied_handler),
+        )
+        .branch(
+            dptree::filter(|msg: Message| !msg.text().is_some_and(|text| text.starts_with('/')))
                 .endpoint(invalid_command_handler),
         );
+    let handler = dptree::entry().branch(messages).branch(
+        Update::filter_callback_query()
+            .branch(
+                dptree::filter(|query: CallbackQuery| {
+                    query
+                        .data
+                        .as_deref()
+                        .is_some_and(|data| data.starts_with(MENU_CALLBACK_PREFIX))
+                })
+                .endpoint(menu_callback_handler),
+            )
+            .endpoint(access_callback_handler),
+    );
 
     bot.send_message(ChatId(telegram_config.admin_user_id), "Bot started.")
         .send()
         .await
         .expect("Failed to send message to admin user");
 
+    tokio::spawn(watch_bandwidth(bot.clone(), Arc::new(context.clone())));
+    tokio::spawn(flush_notifications(bot.clone(), Arc::new(context.clone())));
+    if context.variables().config.motion.enabled {
+        tokio::spawn(watch_motion(bot.clone(), Arc::new(context.clone())));
+    }
+
     Dispatcher::builder(bot, handler)
         .dependencies(dptree::deps![Arc::new(context.clone())])
         .enable_ctrlc_handler()
@@ -143,171 +216,1403 @@ async fn bot(context: &Context<WorkerMessage, Variables>) {
         .await;
 }
 
+fn resolve_caller(msg: Message, context: Arc<Context<WorkerMessage, Variables>>) -> Caller {
+    // commands in channels have no sender
+    let user_id = msg.from.as_ref().map_or(0, |user| user.id.0 as i64);
+    let role = msg.from.as_ref().and_then(|_| context.variables().users.role_of(user_id));
+    Caller { user_id, role }
+}
+
+async fn permission_denied_handler(
+    bot: Bot,
+    msg: Message,
+    cmd: Command,
+    caller: Caller,
+    context: Arc<Context<WorkerMessage, Variables>>,
+) -> ResponseResult<()> {
+    warn!(
+        user_id = caller.user_id,
+        role = ?caller.role,
+        required = ?cmd.required_role(),
+        "User not allowed to use this command. Chat id: {:?}",
+        msg.chat.id
+    );
+    let text = if caller.role.is_none() {
+        "You are not allowed to use this command. Use /requestaccess to ask the admin for access."
+    } else {
+        "You are not allowed to use this command."
+    };
+    bot.send_message(msg.chat.id, text).await?;
+
+    if let Some(user) = msg.from.as_ref() {
+        notify_admin(
+            &bot,
+            &context,
+            NotifyEvent::Unauthorized,
+            Some(caller.user_id),
+            &format!(
+                "{} (id {}) is not allowed to use: {}",
+                user_label(user),
+                caller.user_id,
+                msg.text().unwrap_or_default()
+            ),
+        )
+        .await;
+    }
+    Ok(())
+}
+
 async fn command_handler(
     bot: Bot,
     msg: Message,
     cmd: Command,
+    caller: Caller,
     context: Arc<Context<WorkerMessage, Variables>>,
 ) -> ResponseResult<()> {
-    let hc = context
-        .hub()
-        .register("bot: command handler", event_matches!(WorkerMessage::Frame(_)))
-        .unwrap();
-    let allowed_user_ids = &context.variables().telegram_config.allowed_user_ids;
+    run_command(&bot, msg.chat.id, msg.from.as_ref(), cmd, caller, &context).await
+}
+
+/// Runs a permitted command, sent as a message or pressed in the `/menu` panel.
+async fn run_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    user: Option<&User>,
+    cmd: Command,
+    caller: Caller,
+    context: &Arc<Context<WorkerMessage, Variables>>,
+) -> ResponseResult<()> {
     match cmd {
         Command::Help => {
             let text = Command::descriptions().to_string();
-            bot.send_message(msg.chat.id, text).await?;
+            bot.send_message(chat_id, text).await?;
         }
-        Command::Photo => {
-            info!("Received photo command from chat id: {:?}.", msg.chat.id);
+        Command::Photo(camera) => {
+            info!("Received photo command from chat id: {:?}.", chat_id);
 
-            let user = msg.from.as_ref();
             if let Some(user) = user {
                 info!("User: {:?}", user);
             }
 
-            if should_notify_admin() && user.is_some() {
-                let user = user.clone().unwrap();
+            if let Some(user) = user {
+                notify_admin(
+                    bot,
+                    context,
+                    NotifyEvent::Command,
+                    Some(caller.user_id),
+                    &format!("Received photo command from {}.", user_label(user)),
+                )
+                .await;
+            }
+
+            info!("Received photo command from user: {:?}. Capturing photo...", caller.user_id);
+
+            let camera = camera.trim();
+            let Some(camera) = context.variables().config.camera(camera) else {
                 bot.send_message(
-                    ChatId(context.variables().telegram_config.admin_user_id),
+                    chat_id,
                     format!(
-                        "Received photo command from chat id: {:?}. Username: {:?}",
-                        user.id, user.username
+                        "Unknown camera: {}. Available cameras: {}",
+                        camera,
+                        context.variables().config.camera_names().join(", ")
                     ),
                 )
                 .await?;
+                return Ok(());
+            };
+            let telegram_config = &context.variables().config.telegram_config;
+            let frame = context
+                .variables()
+                .frame_cache
+                .next_frame(&camera.name, telegram_config.photo_skip_frames, telegram_config.photo_timeout)
+                .await;
+            match frame {
+                Ok(frame) => {
+                    bot.send_photo(chat_id, InputFile::memory(frame.data.to_vec())).await?;
+                }
+                Err(e) => {
+                    warn!("Failed to capture photo: {}", e);
+                    notify_admin(
+                        bot,
+                        context,
+                        NotifyEvent::Error,
+                        None,
+                        &format!("Failed to capture photo from camera {}: {}", camera.name, e),
+                    )
+                    .await;
+                    bot.send_message(chat_id, format!("Failed to capture photo: {e}. Please try again later."))
+                        .await?;
+                }
+            }
+        }
+        Command::Burst(args) => {
+            info!("Received burst command from chat id: {:?}.", chat_id);
+            if let Some(user) = user {
+                notify_admin(
+                    bot,
+                    context,
+                    NotifyEvent::Command,
+                    Some(caller.user_id),
+                    &format!("Received burst command from {}.", user_label(user)),
+                )
+                .await;
             }
 
-            let id = user.clone().unwrap().id.0 as i64;
-            if allowed_user_ids.contains(&id) {
-                info!(
-                    "Received photo command from user: {:?}. Capturing photo...",
-                    user.unwrap().username
-                );
+            let telegram_config = &context.variables().config.telegram_config;
+            let (camera, args) = split_camera_arg(&args);
+            let mut args = args.split_whitespace().map(str::parse::<u64>);
+            let (count, interval) = match (args.next(), args.next(), args.next()) {
+                (None, None, None) => (DEFAULT_BURST_FRAMES, DEFAULT_BURST_INTERVAL),
+                (Some(Ok(count)), None, None) => (count, DEFAULT_BURST_INTERVAL),
+                (Some(Ok(count)), Some(Ok(interval)), None) => (count, Duration::from_millis(interval)),
+                _ => {
+                    bot.send_message(chat_id, "Usage: /burst [camera] [count] [interval_ms]").await?;
+                    return Ok(());
+                }
+            };
+            let count = usize::try_from(count)
+                .unwrap_or(usize::MAX)
+                .clamp(1, telegram_config.burst_max_frames);
+            let interval = interval.clamp(telegram_config.burst_min_interval, MAX_BURST_INTERVAL);
+            let Some(camera) = context.variables().config.camera(camera) else {
+                bot.send_message(
+                    chat_id,
+                    format!(
+                        "Unknown camera: {}. Available cameras: {}",
+                        camera,
+                        context.variables().config.camera_names().join(", ")
+                    ),
+                )
+                .await?;
+                return Ok(());
+            };
 
-                // Wait for a frame from the hub
-                if let Ok(WorkerMessage::Frame(frame_data)) = hc.try_recv() {
-                    bot.send_photo(msg.chat.id, InputFile::memory(frame_data)).await?;
-                } else {
-                    bot.send_message(msg.chat.id, "Failed to capture photo. Please try again later.")
+            match capture_burst(context, &camera.name, count, interval).await {
+                Ok(frames) => send_burst(bot, chat_id, frames).await?,
+                Err(e) => {
+                    warn!("Failed to capture burst: {}", e);
+                    notify_admin(
+                        bot,
+                        context,
+                        NotifyEvent::Error,
+                        None,
+                        &format!("Failed to capture burst from camera {}: {}", camera.name, e),
+                    )
+                    .await;
+                    bot.send_message(chat_id, format!("Failed to capture photos: {e}. Please try again later."))
                         .await?;
                 }
-            } else {
-                warn!("User not allowed to use this command. User id: {:?}", msg.chat.id);
-                bot.send_message(msg.chat.id, "You are not allowed to use this command.")
-                    .await?;
             }
         }
-        Command::GetVideo => {
-            info!("Received get_video command from chat id: {:?}.", msg.chat.id);
+        Command::Clip(args) => {
+            info!("Received clip command from chat id: {:?}.", chat_id);
+            if let Some(user) = user {
+                notify_admin(
+                    bot,
+                    context,
+                    NotifyEvent::Command,
+                    Some(caller.user_id),
+                    &format!("Received clip command from {}.", user_label(user)),
+                )
+                .await;
+            }
 
-            let user = msg.from.as_ref();
-            if should_notify_admin() {
-                if let Some(user) = user {
-                    bot.send_message(
-                        ChatId(context.variables().telegram_config.admin_user_id),
-                        format!(
-                            "Received get_video command from chat id: {:?}. Username: {:?}",
-                            user.id, user.username
-                        ),
+            let (camera, args) = split_camera_arg(&args);
+            let length = match args {
+                "" => DEFAULT_CLIP_LENGTH,
+                secs => match secs.parse::<u64>() {
+                    Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                    _ => {
+                        bot.send_message(chat_id, "Usage: /clip [camera] [seconds]").await?;
+                        return Ok(());
+                    }
+                },
+            };
+            let length = length.min(context.variables().config.telegram_config.clip_max_length);
+            let Some(camera) = context.variables().config.camera(camera) else {
+                bot.send_message(
+                    chat_id,
+                    format!(
+                        "Unknown camera: {}. Available cameras: {}",
+                        camera,
+                        context.variables().config.camera_names().join(", ")
+                    ),
+                )
+                .await?;
+                return Ok(());
+            };
+
+            bot.send_message(chat_id, format!("Recording a {} s clip...", length.as_secs()))
+                .await?;
+            // lets the recorder keep the moment in the `events` mode
+            context.hub().send(WorkerMessage::Event(CameraEvent {
+                camera: camera.name.clone(),
+                kind: EventKind::Clip,
+                frame: None,
+            }));
+            match record_clip(context, &camera.name, length).await {
+                Ok((clip, avi)) => {
+                    let mut caption = describe_clip(&clip, &avi);
+                    if !clip.preroll().is_zero() {
+                        caption.push_str(&format!(", starts {:.0} s before the command", clip.preroll().as_secs_f64()));
+                    }
+                    bot.send_document(chat_id, InputFile::memory(avi).file_name("clip.avi"))
+                        .caption(caption)
+                        .await?;
+                }
+                Err(e) => {
+                    warn!("Failed to record clip: {}", e);
+                    notify_admin(
+                        bot,
+                        context,
+                        NotifyEvent::Error,
+                        None,
+                        &format!("Failed to record clip from camera {}: {}", camera.name, e),
                     )
-                    .await?;
+                    .await;
+                    bot.send_message(chat_id, format!("Failed to record clip: {e}. Please try again later."))
+                        .await?;
                 }
             }
+        }
+        cmd @ (Command::Arm | Command::Disarm) => {
+            let armed = matches!(cmd, Command::Arm);
+            let name = if armed { "arm" } else { "disarm" };
+            info!("Received {} command from chat id: {:?}.", name, chat_id);
+            if let Some(user) = user {
+                notify_admin(
+                    bot,
+                    context,
+                    NotifyEvent::Command,
+                    Some(caller.user_id),
+                    &format!("Received {} command from {}.", name, user_label(user)),
+                )
+                .await;
+            }
+            bot.send_message(chat_id, arm_motion_detection(armed, context)).await?;
+        }
+        Command::Alerts(args) => {
+            let text = configure_alerts(&args, caller, context);
+            bot.send_message(chat_id, text).await?;
+        }
+        Command::Zones(camera) => {
+            let camera = camera.trim();
+            let Some(camera) = context.variables().config.camera(camera) else {
+                bot.send_message(
+                    chat_id,
+                    format!(
+                        "Unknown camera: {}. Available cameras: {}",
+                        camera,
+                        context.variables().config.camera_names().join(", ")
+                    ),
+                )
+                .await?;
+                return Ok(());
+            };
+            let mut text = if camera.zones.is_empty() {
+                format!(
+                    "Camera {} has no zones, motion is detected in the whole picture.",
+                    camera.name
+                )
+            } else {
+                let mut text = format!(
+                    "Zones of camera {}: detection zones are outlined in green, ignore zones in red, darkened parts are not watched.",
+                    camera.name
+                );
+                for (i, zone) in camera.zones.iter().enumerate() {
+                    text.push_str(&format!("\n{}. {}", i + 1, zone));
+                }
+                text
+            };
+            if !context.variables().config.motion.enabled {
+                text.push_str("\nMotion detection is not enabled in the settings.");
+            }
+            if camera.zones.is_empty() {
+                bot.send_message(chat_id, text).await?;
+                return Ok(());
+            }
+            match draw_zones(context, &camera.name, camera.zones.clone()).await {
+                Ok(photo) => {
+                    bot.send_photo(chat_id, InputFile::memory(photo).file_name("zones.jpg"))
+                        .caption(text)
+                        .await?;
+                }
+                Err(e) => {
+                    warn!("Failed to draw zones: {}", e);
+                    bot.send_message(chat_id, format!("Failed to draw the zones: {e}. Please try again later."))
+                        .await?;
+                }
+            }
+        }
+        Command::GetVideo => {
+            info!("Received get_video command from chat id: {:?}.", chat_id);
 
-            debug!("Sending video stream URL to chat id: {:?}", msg.chat.id);
-            debug!("Ngrok is started: {:?}", &context.variables().is_ngrok_started);
-
-            bot.send_message(
-                msg.chat.id,
-                format!("Video stream URL: https://{}/", &context.variables().ngrok_domain),
-            )
-            .await
-            .expect("Could not send message");
+            if let Some(user) = user {
+                notify_admin(
+                    bot,
+                    context,
+                    NotifyEvent::Command,
+                    Some(caller.user_id),
+                    &format!("Received get_video command from {}.", user_label(user)),
+                )
+                .await;
+            }
 
-            if *context.variables().is_ngrok_started.read() {
-                debug!("Ngrok is already started.");
+            let tunnel = &context.variables().tunnel;
+            debug!("Tunnel {} is {}", tunnel.name(), tunnel.status());
 
-                bot.send_message(msg.chat.id, "Ngrok is already started.")
-                    .await
-                    .expect("Could not send message");
+            let bandwidth = &context.variables().bandwidth;
+            if bandwidth.is_refused() {
+                warn!("Monthly data budget is exhausted, video stream refused");
+                bot.send_message(
+                    chat_id,
+                    format!(
+                        "Monthly data budget is exhausted ({} of {} used). Video stream is disabled until next month.",
+                        format_mb(bandwidth.usage().bytes),
+                        format_mb(bandwidth.budget())
+                    ),
+                )
+                .await?;
                 return Ok(());
             }
 
-            *context.variables().is_ngrok_started.write() = true;
+            match tunnel.start().await {
+                Ok(()) => {
+                    info!("Tunnel {} started", tunnel.name());
+                    notify_admin(
+                        bot,
+                        context,
+                        NotifyEvent::Tunnel,
+                        Some(caller.user_id),
+                        &format!("Tunnel {} started.", tunnel.name()),
+                    )
+                    .await;
+                    let session = context.variables().tunnel_sessions.fetch_add(1, Ordering::SeqCst) + 1;
+                    tokio::spawn(watch_tunnel(bot.clone(), context.clone(), chat_id, session));
+                }
+                Err(TunnelError::AlreadyRunning) => {
+                    debug!("Tunnel {} is already started.", tunnel.name());
+                    bot.send_message(chat_id, format!("Tunnel {} is already started.", tunnel.name()))
+                        .await?;
+                }
+                Err(e) => {
+                    error!("Failed to start tunnel {}: {}", tunnel.name(), e);
+                    notify_admin(
+                        bot,
+                        context,
+                        NotifyEvent::Error,
+                        None,
+                        &format!("Failed to start tunnel {}: {}", tunnel.name(), e),
+                    )
+                    .await;
+                    bot.send_message(
+                        chat_id,
+                        format!("Failed to start the video stream: {e}. Please try again later."),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            }
 
-            let ngrok_auth_token = context.variables().ngrok_auth_token.clone();
-            let ngrok_domain = context.variables().ngrok_domain.clone();
-            let server_address = context.variables().server_address.clone();
-            let ngrok_tx: oneshot::Sender<()> = run_ngrok(ngrok_auth_token, ngrok_domain, server_address);
-            {
-                let mut ng_tx = context.variables().ngrok_shutdown_tx.lock().await;
-                *ng_tx = Some(ngrok_tx);
+            debug!("Sending video stream URL to chat id: {:?}", chat_id);
+            let public_url = tunnel.url();
+            let token_query = if context.variables().config.access.require_token {
+                format!("?token={}", context.variables().stream_tokens.issue(caller.user_id))
+            } else {
+                String::new()
+            };
+            let mut text = format!("Video stream URL: {}/{}", public_url, token_query);
+            if context.variables().config.cameras.len() > 1 {
+                for camera in &context.variables().config.cameras {
+                    text.push_str(&format!(
+                        "\n{}: {}/cam/{}{}",
+                        camera.name, public_url, camera.name, token_query
+                    ));
+                }
+            }
+            if context.variables().config.access.require_token {
+                let ttl = context.variables().stream_tokens.ttl();
+                text.push_str(&format!("\nThe link is personal and expires in {}.", format_duration(ttl)));
+            }
+            if let Some(fps) = bandwidth.remote_fps_limit() {
+                text.push_str(&format!(
+                    "\nMonthly data budget is exhausted, remote viewers get {fps} frame(s) per second."
+                ));
             }
+            bot.send_message(chat_id, text).await?;
         }
         Command::StopVideo => {
-            info!("Received stop_video command from chat id: {:?}.", msg.chat.id);
+            info!("Received stop_video command from chat id: {:?}.", chat_id);
 
-            let user = msg.from.as_ref();
-            if should_notify_admin() {
-                if let Some(user) = user {
-                    bot.send_message(
-                        ChatId(context.variables().telegram_config.admin_user_id),
-                        format!(
-                            "Received stop_video command from chat id: {:?}. Username: {:?}",
-                            user.id, user.username
-                        ),
+            if let Some(user) = user {
+                notify_admin(
+                    bot,
+                    context,
+                    NotifyEvent::Command,
+                    Some(caller.user_id),
+                    &format!("Received stop_video command from {}.", user_label(user)),
+                )
+                .await;
+            }
+            debug!("Stopping video stream...");
+
+            // ends the watchdog of the running session
+            context.variables().tunnel_sessions.fetch_add(1, Ordering::SeqCst);
+            match context.variables().tunnel.stop().await {
+                Ok(()) => {
+                    bot.send_message(chat_id, "Video stream stopped.").await?;
+                    notify_admin(
+                        bot,
+                        context,
+                        NotifyEvent::Tunnel,
+                        Some(caller.user_id),
+                        &format!("Tunnel {} stopped.", context.variables().tunnel.name()),
                     )
-                    .await?;
+                    .await;
+                }
+                Err(TunnelError::NotRunning) => {
+                    bot.send_message(chat_id, "Video stream is not running.").await?;
+                }
+                Err(e) => {
+                    error!("Failed to stop tunnel: {}", e);
+                    bot.send_message(chat_id, format!("Failed to stop the video stream: {e}"))
+                        .await?;
+                    notify_admin(bot, context, NotifyEvent::Error, None, &format!("Failed to stop tunnel: {e}")).await;
                 }
             }
-            debug!("Stopping video stream...");
+        }
+        Command::Status => {
+            let report = StatusReport::collect(context.variables());
+            bot.send_message(chat_id, report.to_string()).await?;
+        }
+        Command::Menu => {
+            bot.send_message(chat_id, menu_text(context))
+                .reply_markup(menu_keyboard())
+                .await?;
+        }
+        Command::Settings => {
+            bot.send_message(chat_id, describe_settings(caller, context)).await?;
+        }
+        Command::RequestAccess => request_access(bot, chat_id, user, caller, context).await?,
+        Command::Users => {
+            bot.send_message(chat_id, manage_users(UserCommand::List, context)).await?;
+        }
+        Command::AddUser(args) => {
+            bot.send_message(chat_id, manage_users(UserCommand::Add(&args), context)).await?;
+        }
+        Command::RemoveUser(args) => {
+            bot.send_message(chat_id, manage_users(UserCommand::Remove(&args), context)).await?;
+        }
+        Command::SetRole(args) => {
+            bot.send_message(chat_id, manage_users(UserCommand::SetRole(&args), context)).await?;
+        }
+        Command::Notify(args) => {
+            let text = configure_notifications(&args, context);
+            bot.send_message(chat_id, text).await?;
+        }
+    }
+    Ok(())
+}
 
-            {
-                let mut ngrok_shutdown = context.variables().ngrok_shutdown_tx.lock().await;
+/// User management commands with their unparsed arguments.
+enum UserCommand<'a> {
+    List,
+    Add(&'a str),
+    Remove(&'a str),
+    SetRole(&'a str),
+}
 
-                if let Some(sender) = ngrok_shutdown.take() {
-                    sender.send(()).expect("Failed to send shutdown signal to ngrok.");
+/// Runs the admin commands which change the users, returns the reply.
+fn manage_users(cmd: UserCommand<'_>, context: &Context<WorkerMessage, Variables>) -> String {
+    let users = &context.variables().users;
+    let result = match cmd {
+        UserCommand::List => {
+            let mut text = String::from("Users:");
+            for user in users.list() {
+                text.push_str(&format!("\n{} {}", user.id, user.role));
+                if !user.name.is_empty() {
+                    text.push_str(&format!(" {}", user.name));
+                }
+                if user.source == UserSource::Config {
+                    text.push_str(" (settings)");
+                }
+            }
+            return text;
+        }
+        UserCommand::Add(args) => {
+            let mut args = args.split_whitespace();
+            let (Some(user_id), role) = (args.next().and_then(|id| id.parse::<i64>().ok()), args.next()) else {
+                return "Usage: /adduser <id> [viewer|operator|admin]".to_string();
+            };
+            let Ok(role) = role.map_or(Ok(Role::Viewer), str::parse::<Role>) else {
+                return "Unknown role. Use viewer, operator or admin.".to_string();
+            };
+            users
+                .set_role(user_id, role, "")
+                .map(|()| format!("User {user_id} added as {role}."))
+        }
+        UserCommand::SetRole(args) => {
+            let mut args = args.split_whitespace();
+            let (Some(user_id), Some(role)) = (args.next().and_then(|id| id.parse::<i64>().ok()), args.next()) else {
+                return "Usage: /setrole <id> <viewer|operator|admin>".to_string();
+            };
+            let Ok(role) = role.parse::<Role>() else {
+                return "Unknown role. Use viewer, operator or admin.".to_string();
+            };
+            if users.role_of(user_id).is_none() {
+                return format!("User {user_id} is not known, use /adduser to add them.");
+            }
+            users
+                .set_role(user_id, role, "")
+                .map(|()| format!("User {user_id} is now {role}."))
+        }
+        UserCommand::Remove(args) => {
+            let Ok(user_id) = args.trim().parse::<i64>() else {
+                return "Usage: /removeuser <id>".to_string();
+            };
+            users.remove(user_id).map(|removed| {
+                if removed {
+                    format!("User {user_id} removed.")
                 } else {
-                    bot.send_message(msg.chat.id, "Video stream is not running.").await?;
-
-                    return Ok(());
+                    format!("User {user_id} had no access.")
                 }
+            })
+        }
+    };
+    result.unwrap_or_else(|e| match e {
+        UserError::ConfiguredAdmin => "The admin is set in the settings and can not be changed here.".to_string(),
+        UserError::Save(_) => {
+            error!("Failed to change users: {}", e);
+            format!("Failed to change users: {e}")
+        }
+    })
+}
+
+/// Splits `[camera] [numbers...]` command arguments, the first word names the camera unless it
+/// is a number. The camera is empty (the default one) if not given.
+fn split_camera_arg(args: &str) -> (&str, &str) {
+    let args = args.trim();
+    let (first, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    if first.is_empty() || first.parse::<u64>().is_ok() {
+        ("", args)
+    } else {
+        (first, rest.trim_start())
+    }
+}
+
+/// Captures `count` distinct frames at least `interval` apart.
+async fn capture_burst(
+    context: &Context<WorkerMessage, Variables>,
+    camera: &str,
+    count: usize,
+    interval: Duration,
+) -> Result<Vec<CapturedFrame>, FrameError> {
+    let variables = context.variables();
+    let timeout = variables.config.telegram_config.photo_timeout;
+    let mut frames = Vec::with_capacity(count);
+    let mut next = Instant::now();
+    for _ in 0 .. count {
+        tokio::time::sleep_until(next).await;
+        // waits for a frame captured after the previous one
+        frames.push(variables.frame_cache.next_frame(camera, 0, timeout).await?);
+        next = Instant::now() + interval;
+    }
+    Ok(frames)
+}
+
+/// Sends the frames as an album captioned with their capture times.
+async fn send_burst(bot: &Bot, chat_id: ChatId, frames: Vec<CapturedFrame>) -> ResponseResult<()> {
+    let total = frames.len();
+    let mut photos: Vec<InputMediaPhoto> = frames
+        .into_iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let photo = InputFile::memory(frame.data.to_vec()).file_name(format!("burst-{}.jpg", i + 1));
+            InputMediaPhoto::new(photo).caption(format!("{}/{} {}", i + 1, total, format_time(frame.captured_at)))
+        })
+        .collect();
+    // albums need at least two items
+    if let [photo] = photos.as_mut_slice() {
+        let mut request = bot.send_photo(chat_id, photo.media.clone());
+        if let Some(caption) = photo.caption.take() {
+            request = request.caption(caption);
+        }
+        request.await?;
+    } else {
+        bot.send_media_group(chat_id, photos.into_iter().map(InputMedia::Photo))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Records a clip from the hub and encodes it as AVI, both off the async runtime.
+async fn record_clip(
+    context: &Context<WorkerMessage, Variables>,
+    camera: &str,
+    length: Duration,
+) -> Result<(Clip, Vec<u8>), Box<dyn std::error::Error + Send + Sync>> {
+    let telegram_config = &context.variables().config.telegram_config;
+    let timeout = telegram_config.photo_timeout;
+    let max_bytes = telegram_config.clip_max_mb * BYTES_PER_MB;
+    let hub = context.hub().clone();
+    let preroll = context.variables().preroll.clone();
+    let camera = camera.to_string();
+    tokio::task::spawn_blocking(move || {
+        let clip = clip::record(&hub, &preroll, &camera, length, timeout, max_bytes)?;
+        let avi = clip.to_avi()?;
+        Ok((clip, avi))
+    })
+    .await?
+}
+
+/// Takes a photo and draws the motion detection zones on it off the async runtime.
+async fn draw_zones(
+    context: &Context<WorkerMessage, Variables>,
+    camera: &str,
+    zones: Vec<MotionZone>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let telegram_config = &context.variables().config.telegram_config;
+    let frame = context
+        .variables()
+        .frame_cache
+        .next_frame(camera, telegram_config.photo_skip_frames, telegram_config.photo_timeout)
+        .await?;
+    tokio::task::spawn_blocking(move || motion::draw_zones(&frame.data, &zones)).await?
+}
+
+/// Length, frame rate, frame size and file size of a recorded clip.
+fn describe_clip(clip: &Clip, avi: &[u8]) -> String {
+    let (width, height) = clip.dimensions().unwrap_or_default();
+    format!(
+        "{:.0} s, {:.1} fps, {}x{}, {}",
+        clip.duration().as_secs_f64(),
+        clip.fps(),
+        width,
+        height,
+        format_mb(avi.len() as u64)
+    )
+}
+
+/// Runs `/arm` and `/disarm`, returns the reply.
+fn arm_motion_detection(armed: bool, context: &Context<WorkerMessage, Variables>) -> String {
+    let variables = context.variables();
+    if !variables.config.motion.enabled {
+        return "Motion detection is not enabled in the settings.".to_string();
+    }
+    match variables.motion.set_armed(armed) {
+        Ok(()) if armed => {
+            info!("Motion detection armed");
+            format!(
+                "Motion detection armed on camera(s): {}.",
+                variables.config.camera_names().join(", ")
+            )
+        }
+        Ok(()) => {
+            info!("Motion detection disarmed");
+            "Motion detection disarmed.".to_string()
+        }
+        Err(e) => {
+            error!("Failed to save the motion detection state: {}", e);
+            format!("Failed to save the motion detection state: {e}")
+        }
+    }
+}
+
+/// Runs `/alerts`, returns the reply.
+fn configure_alerts(args: &str, caller: Caller, context: &Context<WorkerMessage, Variables>) -> String {
+    let variables = context.variables();
+    let motion = &variables.motion;
+    let result = match args.trim() {
+        "" => Ok(()),
+        "on" => motion.subscribe(caller.user_id, true),
+        "off" => motion.subscribe(caller.user_id, false),
+        _ => return "Usage: /alerts [on|off]".to_string(),
+    };
+    if let Err(e) = result {
+        error!("Failed to save the motion alerts: {}", e);
+        return format!("Failed to save the motion alerts: {e}");
+    }
+    let state = if motion.is_subscribed(caller.user_id) { "on" } else { "off" };
+    let mut text = format!("Your motion alerts: {state}");
+    if !variables.config.motion.enabled {
+        text.push_str(", motion detection is not enabled in the settings");
+    } else if !motion.is_armed() {
+        text.push_str(", motion detection is disarmed");
+    }
+    text.push('.');
+    text
+}
+
+/// Time of day in UTC with milliseconds, like `12:30:05.250 UTC`.
+fn format_time(time: SystemTime) -> String {
+    let millis = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() % 86_400_000;
+    format!(
+        "{:02}:{:02}:{:02}.{:03} UTC",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+fn menu_keyboard() -> InlineKeyboardMarkup {
+    let button = |label: &str, action: &str| InlineKeyboardButton::callback(label, format!("{MENU_CALLBACK_PREFIX}{action}"));
+    InlineKeyboardMarkup::new([
+        vec![button("Photo", "photo"), button("Burst", "burst"), button("Clip", "clip")],
+        vec![button("Start stream", "start"), button("Stop stream", "stop")],
+        vec![button("Arm", "arm"), button("Disarm", "disarm")],
+        vec![button("Status", "status"), button("Settings", "settings")],
+    ])
+}
+
+/// Command of a panel button, the same permissions apply.
+fn menu_command(action: &str) -> Option<Command> {
+    match action {
+        "photo" => Some(Command::Photo(String::new())),
+        "burst" => Some(Command::Burst(String::new())),
+        "clip" => Some(Command::Clip(String::new())),
+        "start" => Some(Command::GetVideo),
+        "stop" => Some(Command::StopVideo),
+        "arm" => Some(Command::Arm),
+        "disarm" => Some(Command::Disarm),
+        "status" => Some(Command::Status),
+        "settings" => Some(Command::Settings),
+        _ => None,
+    }
+}
+
+fn menu_text(context: &Context<WorkerMessage, Variables>) -> String {
+    let variables = context.variables();
+    format!(
+        "Control panel\nVideo stream ({}): {}\nViewers: {}\nMotion detection: {}",
+        variables.tunnel.name(),
+        variables.tunnel.status(),
+        variables.active_viewers.load(Ordering::SeqCst),
+        motion_state(context)
+    )
+}
+
+fn motion_state(context: &Context<WorkerMessage, Variables>) -> &'static str {
+    let variables = context.variables();
+    if !variables.config.motion.enabled {
+        "off"
+    } else if variables.motion.is_armed() {
+        "armed"
+    } else {
+        "disarmed"
+    }
+}
+
+/// Handles the `/menu` panel buttons and refreshes the panel afterwards.
+async fn menu_callback_handler(
+    bot: Bot,
+    query: CallbackQuery,
+    context: Arc<Context<WorkerMessage, Variables>>,
+) -> ResponseResult<()> {
+    let user_id = query.from.id.0 as i64;
+    let caller = Caller {
+        user_id,
+        role: context.variables().users.role_of(user_id),
+    };
+    let action = query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(MENU_CALLBACK_PREFIX))
+        .unwrap_or_default();
+    let Some(cmd) = menu_command(action) else {
+        bot.answer_callback_query(query.id).await?;
+        return Ok(());
+    };
+    if !caller.can_run(&cmd) {
+        warn!(
+            user_id,
+            role = ?caller.role,
+            required = ?cmd.required_role(),
+            "User not allowed to use the panel button {}",
+            action
+        );
+        bot.answer_callback_query(query.id)
+            .text("You are not allowed to use this command.")
+            .show_alert(true)
+            .await?;
+        notify_admin(
+            &bot,
+            &context,
+            NotifyEvent::Unauthorized,
+            Some(user_id),
+            &format!(
+                "{} (id {}) is not allowed to use the panel button: {}",
+                user_label(&query.from),
+                user_id,
+                action
+            ),
+        )
+        .await;
+        return Ok(());
+    }
+    // starting the tunnel takes a while, the button must not spin meanwhile
+    bot.answer_callback_query(query.id).await?;
+
+    // an old panel may be inaccessible, the reply goes to the user then
+    let chat_id = query.message.as_ref().map_or(ChatId(user_id), |message| message.chat().id);
+    run_command(&bot, chat_id, Some(&query.from), cmd, caller, &context).await?;
+
+    if let Some(message) = query.message {
+        let edited = bot
+            .edit_message_text(message.chat().id, message.id(), menu_text(&context))
+            .reply_markup(menu_keyboard())
+            .await;
+        if let Err(e) = edited {
+            // also fails if the text has not changed
+            debug!("Control panel not updated: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn describe_settings(caller: Caller, context: &Context<WorkerMessage, Variables>) -> String {
+    let config = &context.variables().config;
+    let mut text = format!(
+        "Your role: {}\nCameras: {}\nTunnel: {}",
+        caller.role.map_or_else(|| "none".to_string(), |role| role.to_string()),
+        config.camera_names().join(", "),
+        context.variables().tunnel.name()
+    );
+    if !config.tunnel_idle_timeout.is_zero() {
+        text.push_str(&format!(
+            ", stops after {} without viewers",
+            format_duration(config.tunnel_idle_timeout)
+        ));
+    }
+    if !config.tunnel_max_session.is_zero() {
+        text.push_str(&format!(", sessions up to {}", format_duration(config.tunnel_max_session)));
+    }
+    if config.access.require_token {
+        text.push_str(&format!(
+            "\nStream links: personal, valid for {}",
+            format_duration(config.access.token_ttl)
+        ));
+    } else {
+        text.push_str("\nStream links: not protected");
+    }
+    if config.recording.enabled {
+        text.push_str(&format!(
+            "\nRecording: {}, {} segments, {} stored",
+            config.recording.mode,
+            format_duration(config.recording.segment_length),
+            format_mb(context.variables().recordings.total_bytes())
+        ));
+    } else {
+        text.push_str("\nRecording: off");
+    }
+    let motion = &config.motion;
+    text.push_str(&format!("\nMotion detection: {}", motion_state(context)));
+    if motion.enabled {
+        text.push_str(&format!(
+            ", sensitivity {}, {}% of the picture, alerts at most every {}, your alerts {}",
+            motion.sensitivity,
+            motion.min_area_percent,
+            format_duration(motion.cooldown),
+            if context.variables().motion.is_subscribed(caller.user_id) {
+                "on"
+            } else {
+                "off"
             }
+        ));
+    }
+    let bandwidth = &context.variables().bandwidth;
+    if bandwidth.budget() > 0 {
+        text.push_str(&format!(
+            "\nData budget: {} of {} used this month",
+            format_mb(bandwidth.usage().bytes),
+            format_mb(bandwidth.budget())
+        ));
+    } else {
+        text.push_str("\nData budget: unlimited");
+    }
+    if caller.role == Some(Role::Admin) {
+        text.push_str("\n\n");
+        text.push_str(&describe_notifications(context));
+    }
+    text
+}
+
+/// Runs `/notify`, returns the reply.
+fn configure_notifications(args: &str, context: &Context<WorkerMessage, Variables>) -> String {
+    const USAGE: &str = "Usage: /notify [<event>|all|own on|off] [quiet <HH:MM-HH:MM>|off] [digest <secs>] [reset]";
+    let notifier = &context.variables().notifier;
+    let args: Vec<&str> = args.split_whitespace().collect();
+    let result = match args.as_slice() {
+        [] => Ok(()),
+        ["reset"] => notifier.reset().map_err(|e| e.to_string()),
+        ["quiet", "off"] => notifier
+            .update(|settings| settings.quiet_hours = None)
+            .map_err(|e| e.to_string()),
+        ["quiet", period] => {
+            let Ok(quiet_hours) = period.parse::<QuietHours>() else {
+                return format!("Expected a {}.", QuietHours::EXPECTED);
+            };
+            notifier
+                .update(|settings| settings.quiet_hours = Some(quiet_hours))
+                .map_err(|e| e.to_string())
+        }
+        ["digest", secs] => {
+            let Ok(secs) = secs.parse::<u64>() else {
+                return "Expected the digest window in seconds, 0 disables.".to_string();
+            };
+            notifier
+                .update(|settings| settings.digest_window = Duration::from_secs(secs))
+                .map_err(|e| e.to_string())
+        }
+        [target, state @ ("on" | "off")] => {
+            let on = *state == "on";
+            let events = match *target {
+                "own" => {
+                    return match notifier.update(|settings| settings.own_actions = on) {
+                        Ok(()) => describe_notifications(context),
+                        Err(e) => format!("Failed to save notification settings: {e}"),
+                    };
+                }
+                "all" => NotifyEvent::ALL.to_vec(),
+                event => match event.parse::<NotifyEvent>() {
+                    Ok(event) => vec![event],
+                    Err(()) => return format!("Unknown event. Use one of: {}, all, own.", NotifyEvent::EXPECTED),
+                },
+            };
+            notifier
+                .update(|settings| {
+                    settings.events.retain(|event| !events.contains(event));
+                    if on {
+                        settings.events.extend(events);
+                    }
+                })
+                .map_err(|e| e.to_string())
+        }
+        _ => return USAGE.to_string(),
+    };
+    match result {
+        Ok(()) => describe_notifications(context),
+        Err(e) => {
+            error!("Failed to save notification settings: {}", e);
+            format!("Failed to save notification settings: {e}")
+        }
+    }
+}
+
+fn describe_notifications(context: &Context<WorkerMessage, Variables>) -> String {
+    let settings = context.variables().notifier.settings();
+    let mut text = String::from("Notifications:");
+    for event in NotifyEvent::ALL {
+        let state = if settings.events.contains(&event) { "on" } else { "off" };
+        text.push_str(&format!("\n{event}: {state}"));
+    }
+    text.push_str(&format!("\nown actions: {}", if settings.own_actions { "on" } else { "off" }));
+    match settings.quiet_hours {
+        Some(quiet_hours) => text.push_str(&format!(
+            "\nquiet hours: {} (UTC{:+03}:{:02})",
+            quiet_hours,
+            settings.utc_offset_minutes / 60,
+            settings.utc_offset_minutes.abs() % 60
+        )),
+        None => text.push_str("\nquiet hours: off"),
+    }
+    text.push_str(&format!("\ndigest window: {} s", settings.digest_window.as_secs()));
+    text
+}
+
+/// Forwards an access request of an unknown user to the admin with approve and deny buttons.
+async fn request_access(
+    bot: &Bot,
+    chat_id: ChatId,
+    user: Option<&User>,
+    caller: Caller,
+    context: &Context<WorkerMessage, Variables>,
+) -> ResponseResult<()> {
+    let Some(user) = user else {
+        return Ok(());
+    };
+    if let Some(role) = caller.role {
+        bot.send_message(chat_id, format!("You already have access as {role}."))
+            .await?;
+        return Ok(());
+    }
+    let name = user_label(user);
+    if !context.variables().users.add_request(caller.user_id, &name) {
+        bot.send_message(chat_id, "Your request is waiting for the admin.").await?;
+        return Ok(());
+    }
+    info!(user_id = caller.user_id, "Access requested by {}", name);
+    let data = |action: &str| format!("access:{}:{}", caller.user_id, action);
+    let keyboard = InlineKeyboardMarkup::new([
+        vec![
+            InlineKeyboardButton::callback("Approve as viewer", data("viewer")),
+            InlineKeyboardButton::callback("Approve as operator", data("operator")),
+        ],
+        vec![InlineKeyboardButton::callback("Deny", data("deny"))],
+    ]);
+    bot.send_message(
+        ChatId(context.variables().config.telegram_config.admin_user_id),
+        format!("Access requested by {} (id {}).", name, caller.user_id),
+    )
+    .reply_markup(keyboard)
+    .await?;
+    bot.send_message(chat_id, "Your request has been sent to the admin.").await?;
+    Ok(())
+}
 
-            *context.variables().is_ngrok_started.write() = false;
-            bot.send_message(msg.chat.id, "Video stream stopped.").await?;
+/// Handles the buttons of an access request, only the admin may press them.
+async fn access_callback_handler(
+    bot: Bot,
+    query: CallbackQuery,
+    context: Arc<Context<WorkerMessage, Variables>>,
+) -> ResponseResult<()> {
+    let users = &context.variables().users;
+    let request = query.data.as_deref().and_then(|data| {
+        let (user_id, action) = data.strip_prefix("access:")?.split_once(':')?;
+        Some((user_id.parse::<i64>().ok()?, action))
+    });
+    let Some((user_id, action)) = request else {
+        bot.answer_callback_query(query.id).await?;
+        return Ok(());
+    };
+    if users.role_of(query.from.id.0 as i64) != Some(Role::Admin) {
+        warn!(user_id = query.from.id.0, "Access request answered by a non-admin user");
+        bot.answer_callback_query(query.id)
+            .text("Only the admin can do this.")
+            .await?;
+        return Ok(());
+    }
+    let Some(name) = users.take_request(user_id) else {
+        bot.answer_callback_query(query.id)
+            .text("The request has been answered already.")
+            .await?;
+        return Ok(());
+    };
+
+    let (admin_text, user_text) = match action.parse::<Role>() {
+        Ok(role) => match users.set_role(user_id, role, &name) {
+            Ok(()) => (
+                format!("{name} (id {user_id}) approved as {role}."),
+                Some(format!(
+                    "Your access has been approved, your role is {role}. Use /help to list the commands."
+                )),
+            ),
+            Err(e) => {
+                error!("Failed to add user {}: {}", user_id, e);
+                (format!("Failed to add {name} (id {user_id}): {e}"), None)
+            }
+        },
+        Err(()) => {
+            info!(user_id, "Access request denied");
+            (
+                format!("{name} (id {user_id}) denied."),
+                Some("Your access request has been denied.".to_string()),
+            )
+        }
+    };
+    bot.answer_callback_query(query.id).await?;
+    if let Some(message) = query.message {
+        bot.edit_message_text(message.chat().id, message.id(), admin_text).await?;
+    }
+    if let Some(text) = user_text {
+        // the user may have blocked the bot since
+        if let Err(e) = bot.send_message(ChatId(user_id), text).await {
+            warn!("Failed to notify user {}: {}", user_id, e);
         }
     }
     Ok(())
 }
 
+fn user_label(user: &User) -> String {
+    match user.username {
+        Some(ref username) => format!("{} (@{})", user.full_name(), username),
+        None => user.full_name(),
+    }
+}
+
 async fn invalid_command_handler(bot: Bot, msg: Message, context: Arc<Context<WorkerMessage, Variables>>) -> ResponseResult<()> {
     info!("Received invalid command from chat id: {:?}.", msg.chat.id);
     let response = "You entered an invalid command. Please use /photo to request a photo or /getvideo to get a video stream URL.";
     bot.send_message(msg.chat.id, response).await?;
 
-    notify_admin_about_invalid_command(&bot, &msg, context.variables().telegram_config.admin_user_id).await?;
+    notify_admin_about_invalid_command(&bot, &msg, &context).await;
 
     Ok(())
 }
 
-async fn notify_admin_about_invalid_command(bot: &Bot, msg: &Message, admin_id: i64) -> ResponseResult<()> {
-    if should_notify_admin() {
-        let admin_message = format!(
-            "Invalid command received. User ID: {}, Username: {}, Command: {}",
-            msg.chat.id,
-            msg.chat.username().unwrap_or_default(),
-            msg.text().unwrap_or("No text")
+async fn notify_admin_about_invalid_command(bot: &Bot, msg: &Message, context: &Context<WorkerMessage, Variables>) {
+    let admin_message = format!(
+        "Invalid command received. User ID: {}, Username: {}, Command: {}",
+        msg.chat.id,
+        msg.chat.username().unwrap_or_default(),
+        msg.text().unwrap_or("No text")
+    );
+    let actor = msg.from.as_ref().map(|user| user.id.0 as i64);
+    notify_admin(bot, context, NotifyEvent::InvalidCommand, actor, &admin_message).await;
+}
+
+/// Sends a message to the admin if the notification policy allows it now, it may be held for a digest.
+async fn notify_admin(
+    bot: &Bot,
+    context: &Context<WorkerMessage, Variables>,
+    event: NotifyEvent,
+    actor: Option<i64>,
+    text: &str,
+) {
+    let variables = context.variables();
+    let Some(text) = variables.notifier.notify(event, actor, text) else {
+        return;
+    };
+    let admin_chat_id = ChatId(variables.config.telegram_config.admin_user_id);
+    if let Err(e) = bot.send_message(admin_chat_id, text).await {
+        warn!("Failed to notify admin: {}", e);
+    }
+}
+
+/// Sends the held admin notifications as a digest after the quiet hours or the digest window.
+async fn flush_notifications(bot: Bot, context: Arc<Context<WorkerMessage, Variables>>) {
+    let variables = context.variables();
+    let admin_chat_id = ChatId(variables.config.telegram_config.admin_user_id);
+    let mut interval = tokio::time::interval(NOTIFY_FLUSH_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(text) = variables.notifier.digest() else {
+            continue;
+        };
+        if let Err(e) = bot.send_message(admin_chat_id, text).await {
+            warn!("Failed to send notification digest to admin: {}", e);
+        }
+    }
+}
+
+/// Sends the motion events of [`crate::workers::MotionDetector`] to the users subscribed by
+/// `/alerts` as a photo, followed by a clip recorded in the background if
+/// [`MotionConfig::clip_length`] is set.
+///
+/// [`MotionConfig::clip_length`]: crate::config::MotionConfig::clip_length
+async fn watch_motion(bot: Bot, context: Arc<Context<WorkerMessage, Variables>>) {
+    let client = context.hub().register(
+        "telegram: motion",
+        |msg: &WorkerMessage| matches!(msg, WorkerMessage::Event(event) if matches!(event.kind, EventKind::Motion { .. })),
+    );
+    let client = match client {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to subscribe to motion events: {}", e);
+            return;
+        }
+    };
+    // while an alert is sent, newer events wait in the hub queue, which keeps the latest one per camera
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    tokio::task::spawn_blocking(move || {
+        while let Ok(msg) = client.recv() {
+            if let WorkerMessage::Event(event) = msg {
+                if tx.blocking_send(event).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    while let Some(event) = rx.recv().await {
+        send_motion_alert(&bot, &context, event).await;
+    }
+}
+
+async fn send_motion_alert(bot: &Bot, context: &Arc<Context<WorkerMessage, Variables>>, event: CameraEvent) {
+    let variables = context.variables();
+    let EventKind::Motion { area_percent } = event.kind else {
+        return;
+    };
+    // disarmed since the detection
+    if !variables.motion.is_armed() {
+        return;
+    }
+    let mut subscribers = variables.motion.subscribers();
+    // removed users keep no alerts
+    subscribers.retain(|&user_id| variables.users.role_of(user_id).is_some());
+    if subscribers.is_empty() {
+        return;
+    }
+    let text = format!(
+        "Motion on camera {} at {}, {:.1}% of the picture changed.",
+        event.camera,
+        format_time(SystemTime::now()),
+        area_percent
+    );
+    for &user_id in &subscribers {
+        let sent = match event.frame {
+            Some(ref frame) => bot
+                .send_photo(ChatId(user_id), InputFile::memory(frame.to_vec()).file_name("motion.jpg"))
+                .caption(text.clone())
+                .await
+                .map(|_| ()),
+            None => bot.send_message(ChatId(user_id), text.clone()).await.map(|_| ()),
+        };
+        if let Err(e) = sent {
+            warn!("Failed to send motion alert to user {}: {}", user_id, e);
+        }
+    }
+
+    let length = variables.config.motion.clip_length;
+    if length.is_zero() {
+        return;
+    }
+    // a clip of the camera is still being recorded, it covers this motion as well
+    if !variables.motion.start_clip(&event.camera) {
+        debug!(camera = event.camera, "Motion clip skipped, the previous one is still recorded");
+        return;
+    }
+    // recording takes the clip length, the alerts which follow must not wait for it
+    tokio::spawn(send_motion_clip(bot.clone(), context.clone(), event.camera, subscribers, length));
+}
+
+/// Records and sends a motion clip, started by [`MotionState::start_clip`] for the camera.
+///
+/// [`MotionState::start_clip`]: crate::motion::MotionState::start_clip
+async fn send_motion_clip(
+    bot: Bot,
+    context: Arc<Context<WorkerMessage, Variables>>,
+    camera: String,
+    subscribers: Vec<i64>,
+    length: Duration,
+) {
+    match record_clip(&context, &camera, length).await {
+        Ok((clip, avi)) => {
+            let caption = format!("Motion on camera {}: {}", camera, describe_clip(&clip, &avi));
+            for &user_id in &subscribers {
+                let sent = bot
+                    .send_document(ChatId(user_id), InputFile::memory(avi.clone()).file_name("motion.avi"))
+                    .caption(caption.clone())
+                    .await;
+                if let Err(e) = sent {
+                    warn!("Failed to send motion clip to user {}: {}", user_id, e);
+                }
+            }
+        }
+        Err(e) => {
+            warn!("Failed to record motion clip: {}", e);
+            notify_admin(
+                &bot,
+                &context,
+                NotifyEvent::Error,
+                None,
+                &format!("Failed to record motion clip from camera {}: {}", camera, e),
+            )
+            .await;
+        }
+    }
+    context.variables().motion.finish_clip(&camera);
+}
+
+/// Stops the tunnel after [`Config::tunnel_idle_timeout`] without remote viewers or after
+/// [`Config::tunnel_max_session`], and tells the requesting chat and the admin why.
+///
+/// [`Config::tunnel_idle_timeout`]: crate::config::Config::tunnel_idle_timeout
+/// [`Config::tunnel_max_session`]: crate::config::Config::tunnel_max_session
+async fn watch_tunnel(bot: Bot, context: Arc<Context<WorkerMessage, Variables>>, chat_id: ChatId, session: u64) {
+    let variables = context.variables();
+    let idle_timeout = variables.config.tunnel_idle_timeout;
+    let max_session = variables.config.tunnel_max_session;
+    let started = Instant::now();
+    let mut idle_since = started;
+    let mut interval = tokio::time::interval(TUNNEL_WATCH_INTERVAL);
+
+    let (event, text) = loop {
+        interval.tick().await;
+        if variables.tunnel_sessions.load(Ordering::SeqCst) != session {
+            // stopped by /stopvideo, possibly started again since
+            return;
+        }
+        if variables.tunnel.status() == TunnelStatus::Stopped {
+            warn!("Tunnel {} closed unexpectedly", variables.tunnel.name());
+            break (
+                NotifyEvent::Error,
+                "Video stream closed unexpectedly. Use /getvideo to start it again.".to_string(),
+            );
+        }
+        // LAN viewers do not need the tunnel
+        if variables.remote_viewers.load(Ordering::SeqCst) > 0 {
+            idle_since = Instant::now();
+        }
+        let reason = if !idle_timeout.is_zero() && idle_since.elapsed() >= idle_timeout {
+            format!("no viewers for {}", format_duration(idle_timeout))
+        } else if !max_session.is_zero() && started.elapsed() >= max_session {
+            format!("maximum session length of {} reached", format_duration(max_session))
+        } else {
+            continue;
+        };
+        info!("Stopping tunnel {}: {}", variables.tunnel.name(), reason);
+        variables.tunnel_sessions.fetch_add(1, Ordering::SeqCst);
+        match variables.tunnel.stop().await {
+            Ok(()) | Err(TunnelError::NotRunning) => break (NotifyEvent::Tunnel, format!("Video stream stopped: {reason}.")),
+            Err(e) => {
+                error!("Failed to stop tunnel: {}", e);
+                break (NotifyEvent::Error, format!("Failed to stop the video stream ({reason}): {e}"));
+            }
+        }
+    };
+
+    if let Err(e) = bot.send_message(chat_id, text.clone()).await {
+        warn!("Failed to send tunnel message to chat id {:?}: {}", chat_id, e);
+    }
+    if chat_id != ChatId(variables.config.telegram_config.admin_user_id) {
+        notify_admin(&bot, &context, event, None, &text).await;
+    }
+}
+
+/// Saves the bandwidth totals, warns the admin about budget thresholds and stops the tunnel
+/// when the budget is exhausted in the refuse mode.
+async fn watch_bandwidth(bot: Bot, context: Arc<Context<WorkerMessage, Variables>>) {
+    let variables = context.variables();
+    let admin_chat_id = ChatId(variables.config.telegram_config.admin_user_id);
+    let mut interval = tokio::time::interval(BANDWIDTH_WATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+        let bandwidth = variables.bandwidth.clone();
+        match tokio::task::spawn_blocking(move || bandwidth.save().map_err(|e| e.to_string())).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Failed to save bandwidth totals: {}", e),
+            Err(e) => warn!("Failed to save bandwidth totals: {}", e),
+        }
+
+        let Some(warning) = variables.bandwidth.check_budget() else {
+            continue;
+        };
+        let mut text = format!(
+            "Remote streaming used {}% of the monthly data budget: {} of {}.",
+            warning.percent,
+            format_mb(warning.used),
+            format_mb(warning.budget)
         );
-        bot.send_message(ChatId(admin_id), admin_message).await?;
+        if variables.bandwidth.is_refused() && variables.tunnel.status() == TunnelStatus::Running {
+            info!(
+                "Monthly data budget is exhausted, stopping tunnel {}",
+                variables.tunnel.name()
+            );
+            // ends the idle watchdog of the session
+            variables.tunnel_sessions.fetch_add(1, Ordering::SeqCst);
+            match variables.tunnel.stop().await {
+                Ok(()) | Err(TunnelError::NotRunning) => text.push_str(" Video stream stopped."),
+                Err(e) => {
+                    error!("Failed to stop tunnel: {}", e);
+                    text.push_str(&format!(" Failed to stop the video stream: {e}"));
+                }
+            }
+        } else if let Some(fps) = variables.bandwidth.remote_fps_limit() {
+            text.push_str(&format!(" Remote viewers are limited to {fps} frame(s) per second."));
+        }
+        if let Err(e) = bot.send_message(admin_chat_id, text).await {
+            warn!("Failed to send bandwidth warning to admin: {}", e);
+        }
     }
-    Ok(())
 }
 
-fn should_notify_admin() -> bool {
-    // @todo implement logic here to determine if the admin should be notified about bot activities
-    // For now, we'll always notify
-    true
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs.is_multiple_of(3600) {
+        format!("{} h", secs / 3600)
+    } else if secs.is_multiple_of(60) {
+        format!("{} min", secs / 60)
+    } else {
+        format!("{secs} s")
+    }
 }
@@ -1,19 +1,38 @@
 use crate::prelude::*;
+use crate::recording::Segment;
+use crate::status::StatusReport;
+use axum::body::{Body, Bytes};
 use axum::extract::ws::{Message as WebsocketMessage, WebSocket};
-use axum::extract::{State, WebSocketUpgrade};
-use axum::response::{Html, IntoResponse};
+use axum::extract::{ConnectInfo, Path, Query, Request, State, WebSocketUpgrade};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
 use axum::routing::get;
-use axum::Router;
+use axum::{Json, Router};
+use futures_util::{stream, SinkExt, StreamExt};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
 use roboplc::controller::{Context, WResult, Worker};
-use roboplc::{event_matches, hub};
+use roboplc::hub;
 use roboplc_derive::WorkerOpts;
+use serde::Deserialize;
+use std::convert::Infallible;
 use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::net::UnixListener;
 use tokio::runtime::Runtime;
-use tokio::sync::Mutex;
-use tokio::time::{sleep, Instant};
-use tracing::{debug, error, info};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+/// Frames buffered per viewer, older frames are dropped when a viewer can not keep up.
+const VIEWER_QUEUE_SIZE: usize = 4;
+const MJPEG_BOUNDARY: &str = "frame";
 
 #[derive(WorkerOpts)]
 #[worker_opts(cpu = 2, priority = 70, scheduling = "fifo", blocking = true)]
@@ -24,102 +43,550 @@ impl Worker<WorkerMessage, Variables> for WebSocketWorker {
         let runtime = Runtime::new().unwrap();
 
 
-        runtime.block_on(async {
-            let ngrok_domain = context.variables().ngrok_domain.clone();
-            let hc: Arc<Mutex<hub::Client<WorkerMessage>>> = Arc::new(Mutex::new(
-                context
+        let server_address = context.variables().config.server_address.clone();
+        let served = runtime.block_on(async {
+            let listen_address = server_address.clone();
+            let cameras = context.variables().config.camera_names();
+            let fanout = Arc::new(FrameFanout::new(&cameras, VIEWER_QUEUE_SIZE));
+            let active_viewers = context.variables().active_viewers.clone();
+            let remote_viewers = context.variables().remote_viewers.clone();
+            let mjpeg_max_fps = context.variables().config.mjpeg_max_fps;
+            let frame_cache = context.variables().frame_cache.clone();
+            let bandwidth = context.variables().bandwidth.clone();
+            let tunnel = context.variables().config.tunnel;
+            let tokens = context
+                .variables()
+                .config
+                .access
+                .require_token
+                .then(|| context.variables().stream_tokens.clone());
+            let variables = Variables::clone(context.variables());
+
+            // a single hub client per camera feeds all the viewers
+            for camera in &cameras {
+                let name = camera.clone();
+                let hc = context
                     .hub()
-                    .register("websocket: frame sender", event_matches!(WorkerMessage::Frame(_)))
-                    .unwrap(),
-            ));
+                    .register(&format!("websocket: {camera}"), move |msg: &WorkerMessage| {
+                        msg.is_frame_of(&name)
+                    })
+                    .unwrap();
+                let fanout = fanout.clone();
+                tokio::task::spawn_blocking(move || forward_frames(&hc, &fanout));
+            }
 
             let server_handle = tokio::spawn(async move {
                 let app_state = ServerState {
-                    ws_path: format!("wss://{}/ws", ngrok_domain),
+                    cameras,
+                    fanout,
+                    active_viewers,
+                    remote_viewers,
+                    mjpeg_max_fps,
+                    frame_cache,
+                    bandwidth,
+                    tunnel,
+                    tokens,
+                    variables,
                 };
 
                 let app = Router::new()
                     .route("/", get(index_handler))
-                    .route(
-                        "/ws",
-                        get(move |ws: WebSocketUpgrade| async move {
-                            ws.on_upgrade(move |socket| websocket_handler(socket, hc.clone()))
-                        }),
-                    )
+                    .route("/cam/:name", get(camera_handler))
+                    .route("/ws", get(default_ws_handler))
+                    .route("/ws/:name", get(camera_ws_handler))
+                    .route("/stream.mjpg", get(mjpeg_handler))
+                    .route("/snapshot.jpg", get(snapshot_handler))
+                    .route("/api/status", get(status_handler))
+                    .route("/api/recordings", get(recordings_handler))
+                    .route_layer(middleware::from_fn_with_state(app_state.clone(), require_token))
                     .with_state(app_state);
 
 
-                let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
-                info!("Starting server on http://{}", addr);
-                let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-                axum::serve(listener, app).await.unwrap();
+                info!("Starting server on {}", listen_address);
+                match &listen_address {
+                    ListenAddress::Tcp(addr) => match tokio::net::TcpListener::bind(addr).await {
+                        Ok(listener) => axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await,
+                        Err(e) => Err(e),
+                    },
+                    ListenAddress::Unix(path) => serve_unix(path, app).await,
+                }
             });
-            let _ = tokio::try_join!(server_handle);
+            server_handle.await
         });
 
-        Ok(())
+        // the bot keeps sending stream links, so a dead server must fail the worker
+        match served {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(format!("HTTP server on {server_address} failed: {e}").into()),
+            Err(e) => Err(format!("HTTP server on {server_address} panicked: {e}").into()),
+        }
+    }
+}
+
+/// Serves the app on a Unix domain socket, `axum::serve` only accepts TCP listeners.
+async fn serve_unix(path: &std::path::Path, app: Router) -> std::io::Result<()> {
+    // a socket file left by a previous run would make bind fail, anything else is kept
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(path)?;
+    loop {
+        let (socket, _) = listener.accept().await?;
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            let connection = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(socket), service)
+                .await;
+            if let Err(e) = connection {
+                debug!("Unix socket connection error: {}", e);
+            }
+        });
+    }
+}
+
+/// Moves frames from the hub to the viewers, runs on a blocking thread
+fn forward_frames(hc: &hub::Client<WorkerMessage>, fanout: &FrameFanout) {
+    while let Ok(msg) = hc.recv() {
+        if let WorkerMessage::Frame(frame) = msg {
+            fanout.publish(&frame.camera, Arc::new(frame.data));
+        }
+    }
+}
+
+/// Keeps [`Variables::active_viewers`] and [`Variables::remote_viewers`] up to date for the
+/// lifetime of a viewer connection
+struct ViewerGuard {
+    viewers: Arc<AtomicUsize>,
+    remote_viewers: Option<Arc<AtomicUsize>>,
+    camera: String,
+    kind: &'static str,
+}
+
+impl ViewerGuard {
+    fn new(state: &ServerState, camera: &str, kind: &'static str, remote: bool) -> Self {
+        let active = state.active_viewers.fetch_add(1, Ordering::SeqCst) + 1;
+        let remote_viewers = remote.then(|| state.remote_viewers.clone());
+        if let Some(ref remote_viewers) = remote_viewers {
+            remote_viewers.fetch_add(1, Ordering::SeqCst);
+        }
+        info!(camera, viewers = active, remote, "{} viewer connected", kind);
+        Self {
+            viewers: state.active_viewers.clone(),
+            remote_viewers,
+            camera: camera.to_string(),
+            kind,
+        }
+    }
+}
+
+impl Drop for ViewerGuard {
+    fn drop(&mut self) {
+        let active = self.viewers.fetch_sub(1, Ordering::SeqCst) - 1;
+        if let Some(ref remote_viewers) = self.remote_viewers {
+            remote_viewers.fetch_sub(1, Ordering::SeqCst);
+        }
+        info!(camera = self.camera, viewers = active, "{} viewer disconnected", self.kind);
+    }
+}
+
+async fn default_ws_handler(
+    State(state): State<ServerState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let camera = state.cameras[0].clone();
+    let remote = is_remote(&state, peer, &headers);
+    upgrade_camera_ws(state, camera, ws, remote)
+}
+
+async fn camera_ws_handler(
+    Path(name): Path<String>,
+    State(state): State<ServerState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let remote = is_remote(&state, peer, &headers);
+    upgrade_camera_ws(state, name, ws, remote)
+}
+
+fn upgrade_camera_ws(state: ServerState, camera: String, ws: WebSocketUpgrade, remote: bool) -> Response {
+    if remote && state.bandwidth.is_refused() {
+        return budget_exhausted_response();
+    }
+    let Some(frames) = state.fanout.subscribe(&camera) else {
+        return (StatusCode::NOT_FOUND, "Unknown camera").into_response();
+    };
+    ws.on_upgrade(move |socket| websocket_handler(socket, camera, frames, state, remote))
+}
+
+/// Tells whether a request came through the tunnel and has to be metered.
+///
+/// Tunnels connect from the local host (or through the Unix socket) and proxies like ngrok add
+/// `X-Forwarded-For`, while LAN viewers connect directly.
+fn is_remote(state: &ServerState, peer: Option<ConnectInfo<SocketAddr>>, headers: &HeaderMap) -> bool {
+    if state.tunnel == TunnelKind::Lan {
+        return false;
+    }
+    headers.contains_key("x-forwarded-for") || peer.is_none_or(|ConnectInfo(addr)| addr.ip().is_loopback())
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// Requests through the tunnel must carry the stream token sent by `/getvideo` as `?token=`.
+async fn require_token(State(state): State<ServerState>, request: Request, next: Next) -> Response {
+    let Some(ref tokens) = state.tokens else {
+        return next.run(request).await;
+    };
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().copied();
+    if !is_remote(&state, peer, request.headers()) {
+        return next.run(request).await;
+    }
+    let token = Query::<TokenQuery>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(query)| query.token);
+    let Some(token) = token else {
+        debug!(path = request.uri().path(), "Request without a stream token rejected");
+        return (
+            StatusCode::UNAUTHORIZED,
+            "Stream token required, use /getvideo in the Telegram bot",
+        )
+            .into_response();
+    };
+    match tokens.verify(&token) {
+        // the token outlives the access of a user removed by /removeuser
+        Ok(claims) if state.variables.users.role_of(claims.user_id).is_none() => {
+            debug!(path = request.uri().path(), user_id = claims.user_id, "Stream token of a removed user rejected");
+            (StatusCode::FORBIDDEN, "Access revoked").into_response()
+        }
+        Ok(claims) => {
+            debug!(path = request.uri().path(), user_id = claims.user_id, "Stream token accepted");
+            next.run(request).await
+        }
+        Err(e) => {
+            debug!(path = request.uri().path(), "Stream token rejected: {}", e);
+            (StatusCode::FORBIDDEN, format!("{e}, use /getvideo in the Telegram bot")).into_response()
+        }
     }
 }
 
+fn budget_exhausted_response() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Monthly data budget of remote streaming is exhausted",
+    )
+        .into_response()
+}
+
 /// Handles WebSocket connections
-async fn websocket_handler(mut socket: WebSocket, rx: Arc<Mutex<hub::Client<WorkerMessage>>>) {
-    info!("WebSocket connection established");
-    let hc = rx.lock().await;
+async fn websocket_handler(
+    socket: WebSocket,
+    camera: String,
+    mut frames: broadcast::Receiver<Arc<Vec<u8>>>,
+    state: ServerState,
+    remote: bool,
+) {
+    let _viewer = ViewerGuard::new(&state, &camera, "WebSocket", remote);
+    let (mut sender, mut receiver) = socket.split();
 
-    let mut attempts = 0;
     let start_time = Instant::now();
     let mut frame_count = 0;
     let mut total_bytes = 0;
-    while let Ok(frame) = hc.recv() {
-        if let WorkerMessage::Frame(frame) = frame {
-            frame_count += 1;
-            total_bytes += frame.len();
-            match socket.send(WebsocketMessage::Binary(frame)).await {
-                Ok(_) => {
-                    //@todo write to logs if debug is enabled
+    let mut next_due = Instant::now();
+    loop {
+        tokio::select! {
+            frame = frames.recv() => match frame {
+                Ok(frame) => {
+                    if remote {
+                        // low-rate mode after the monthly budget is exhausted
+                        if let Some(fps) = state.bandwidth.remote_fps_limit() {
+                            let now = Instant::now();
+                            if now < next_due {
+                                continue;
+                            }
+                            next_due = now + Duration::from_secs(1) / fps;
+                        }
+                    }
+                    frame_count += 1;
+                    total_bytes += frame.len();
+                    if let Err(e) = sender.send(WebsocketMessage::Binary(frame.to_vec())).await {
+                        debug!(camera, "Failed to send frame to WebSocket client. Error: {:?}", e);
+                        break;
+                    }
+                    if remote {
+                        state.bandwidth.add(frame.len());
+                    }
                     if frame_count % 120 == 0 {
                         let elapsed = start_time.elapsed();
                         let mb_processed = total_bytes as f64 / (1024.0 * 1024.0);
-                        let average_fps = frame_count as f64 / elapsed.as_secs_f64();
+                        let average_fps = f64::from(frame_count) / elapsed.as_secs_f64();
                         debug!("WS: Average FPS: {:.2}", average_fps);
                         debug!("WS: Elapsed: {:.2}", elapsed.as_secs_f64());
                         debug!("WS: MB processed: {:.2}", mb_processed);
                     }
                 }
-                Err(e) => {
-                    error!("Failed to send frame to WebSocket client. Error: {:?}", e);
-                    if attempts > 5 {
-                        break;
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!(camera, skipped, "WS: viewer is too slow, stale frames dropped");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            incoming = receiver.next() => match incoming {
+                Some(Ok(WebsocketMessage::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MjpegQuery {
+    camera: Option<String>,
+    fps: Option<u32>,
+}
+
+/// MJPEG stream (multipart/x-mixed-replace) for VLC, ffmpeg, Home Assistant and `<img>` tags.
+///
+/// `?camera=` selects the camera, `?fps=` limits the frame rate of this client, capped by
+/// [`ServerState::mjpeg_max_fps`]. Frames arriving faster than the limit are skipped.
+async fn mjpeg_handler(
+    State(state): State<ServerState>,
+    Query(query): Query<MjpegQuery>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> Response {
+    let remote = is_remote(&state, peer, &headers);
+    if remote && state.bandwidth.is_refused() {
+        return budget_exhausted_response();
+    }
+    let camera = query.camera.unwrap_or_else(|| state.cameras[0].clone());
+    let Some(frames) = state.fanout.subscribe(&camera) else {
+        return (StatusCode::NOT_FOUND, "Unknown camera").into_response();
+    };
+    let fps = query.fps.unwrap_or(state.mjpeg_max_fps).clamp(1, state.mjpeg_max_fps);
+    let min_interval = Duration::from_secs(1) / fps;
+    let viewer = ViewerGuard::new(&state, &camera, "MJPEG", remote);
+    let bandwidth = remote.then_some(state.bandwidth);
+    debug!(camera, fps, "MJPEG: frame rate limit");
+
+    let stream = stream::unfold((frames, viewer, Instant::now()), move |(mut frames, viewer, mut next_due)| {
+        let bandwidth = bandwidth.clone();
+        async move {
+            loop {
+                match frames.recv().await {
+                    Ok(frame) => {
+                        let now = Instant::now();
+                        if now < next_due {
+                            continue;
+                        }
+                        // low-rate mode after the monthly budget is exhausted
+                        let interval = bandwidth
+                            .as_ref()
+                            .and_then(|bandwidth| bandwidth.remote_fps_limit())
+                            .map_or(min_interval, |fps| min_interval.max(Duration::from_secs(1) / fps));
+                        // keep the average rate close to the limit, but do not burst after a stall
+                        next_due = (next_due + interval).max(now);
+                        let part = mjpeg_part(&frame);
+                        if let Some(ref bandwidth) = bandwidth {
+                            bandwidth.add(part.len());
+                        }
+                        return Some((Ok::<_, Infallible>(part), (frames, viewer, next_due)));
                     }
-                    attempts += 1;
-                    sleep(Duration::from_secs(1)).await;
+                    Err(RecvError::Lagged(skipped)) => {
+                        debug!(
+                            camera = viewer.camera,
+                            skipped, "MJPEG: viewer is too slow, stale frames dropped"
+                        );
+                    }
+                    Err(RecvError::Closed) => return None,
                 }
             }
         }
+    });
+
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                format!("multipart/x-mixed-replace; boundary={MJPEG_BOUNDARY}"),
+            ),
+            (header::CACHE_CONTROL, "no-cache, no-store, must-revalidate".to_string()),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+fn mjpeg_part(frame: &[u8]) -> Bytes {
+    let mut part = Vec::with_capacity(frame.len() + 128);
+    part.extend_from_slice(
+        format!(
+            "--{MJPEG_BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            frame.len()
+        )
+        .as_bytes(),
+    );
+    part.extend_from_slice(frame);
+    part.extend_from_slice(b"\r\n");
+    Bytes::from(part)
+}
+
+#[derive(Deserialize)]
+struct SnapshotQuery {
+    camera: Option<String>,
+    max_age_ms: Option<u64>,
+}
+
+/// System and pipeline health as JSON, the same data as the `/status` bot command.
+async fn status_handler(State(state): State<ServerState>) -> Json<StatusReport> { Json(StatusReport::collect(&state.variables)) }
+
+#[derive(Deserialize)]
+struct RecordingsQuery {
+    camera: Option<String>,
+    /// Unix time in seconds.
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+/// Recorded segments overlapping `?from=` to `?to=` (Unix time in seconds), all of them by default.
+async fn recordings_handler(State(state): State<ServerState>, Query(query): Query<RecordingsQuery>) -> Json<Vec<Segment>> {
+    let from_ms = query.from.map_or(0, |from| from.saturating_mul(1000));
+    let to_ms = query.to.map_or(u64::MAX, |to| to.saturating_mul(1000));
+    Json(state.variables.recordings.find(query.camera.as_deref(), from_ms, to_ms))
+}
+
+/// Latest frame of a camera as a single JPEG, for dashboards polling stills.
+///
+/// `?max_age_ms=` makes the request fail with 503 if the cached frame is older than that.
+async fn snapshot_handler(
+    State(state): State<ServerState>,
+    Query(query): Query<SnapshotQuery>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> Response {
+    let remote = is_remote(&state, peer, &headers);
+    if remote && state.bandwidth.is_refused() {
+        return budget_exhausted_response();
+    }
+    let camera = query.camera.unwrap_or_else(|| state.cameras[0].clone());
+    if !state.cameras.contains(&camera) {
+        return (StatusCode::NOT_FOUND, "Unknown camera").into_response();
+    }
+    let Some(frame) = state.frame_cache.latest(&camera) else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "No frame captured yet").into_response();
+    };
+
+    let age = frame.captured_at.elapsed().unwrap_or_default();
+    if let Some(max_age_ms) = query.max_age_ms {
+        if age > Duration::from_millis(max_age_ms) {
+            debug!(camera, age_ms = age.as_millis(), max_age_ms, "Snapshot is stale");
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("Latest frame is {} ms old, max_age_ms is {}", age.as_millis(), max_age_ms),
+            )
+                .into_response();
+        }
+    }
+
+    let last_modified = httpdate::fmt_http_date(frame.captured_at);
+    // HTTP dates have one second resolution, the ETag tells apart the frames of the same second;
+    // the capture time keeps the tags unique when the sequence restarts with the program
+    let captured_ms = frame.captured_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let etag = format!("\"{}-{}\"", frame.seq, captured_ms);
+    let not_modified = match headers.get(header::IF_NONE_MATCH) {
+        Some(value) => value
+            .to_str()
+            .is_ok_and(|value| value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*")),
+        None => headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| httpdate::parse_http_date(value).ok())
+            .is_some_and(|since| frame.captured_at <= since),
+    };
+    let headers = [
+        (header::LAST_MODIFIED, last_modified),
+        (header::ETAG, etag),
+        (header::CACHE_CONTROL, "no-cache".to_string()),
+    ];
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
     }
 
-    info!("WebSocket connection closed");
+    if remote {
+        state.bandwidth.add(frame.data.len());
+    }
+    (headers, [(header::CONTENT_TYPE, "image/jpeg")], frame.data.to_vec()).into_response()
 }
 
-/// video stream page handler
-async fn index_handler(State(state): State<ServerState>) -> impl IntoResponse {
-    info!("Received request to / from {}", state.ws_path);
+/// video stream page handler, shows the default camera
+async fn index_handler(State(state): State<ServerState>, Query(query): Query<TokenQuery>) -> impl IntoResponse {
+    let camera = state.cameras[0].clone();
+    info!("Received request to / for camera {}", camera);
+    let token = query.token.unwrap_or_default();
+    Html(stream_page(&state, &camera, "/ws", &token))
+}
 
-    let html = format!(
+/// video stream page handler of a named camera
+async fn camera_handler(
+    Path(name): Path<String>,
+    State(state): State<ServerState>,
+    Query(query): Query<TokenQuery>,
+) -> impl IntoResponse {
+    info!("Received request to /cam/{}", name);
+    if !state.cameras.contains(&name) {
+        return (StatusCode::NOT_FOUND, "Unknown camera").into_response();
+    }
+    let ws_path = format!("/ws/{name}");
+    let token = query.token.unwrap_or_default();
+    Html(stream_page(&state, &name, &ws_path, &token)).into_response()
+}
+
+/// Renders the viewer page, the stream token is passed on to the WebSocket and the camera links.
+///
+/// The WebSocket connects to the host the page was loaded from, so LAN viewers stay on the LAN.
+fn stream_page(state: &ServerState, camera: &str, ws_path: &str, token: &str) -> String {
+    // tokens are URL safe, anything else is dropped instead of being injected into the page
+    let token_query = if !token.is_empty() && token.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) {
+        format!("?token={token}")
+    } else {
+        String::new()
+    };
+    let ws_path = format!("{ws_path}{token_query}");
+    let camera_links = state
+        .cameras
+        .iter()
+        .map(|name| format!(r#"<a href="/cam/{name}{token_query}">{name}</a>"#))
+        .collect::<Vec<_>>()
+        .join(" | ");
+
+    format!(
         r#"<!DOCTYPE html>
                 <html lang="en">
                 <head>
                     <meta charset="UTF-8">
                     <meta name="viewport" content="width=device-width, initial-scale=1.0">
-                    <title>Video Stream</title>
+                    <title>Video Stream - {camera}</title>
                 </head>
                 <body>
-                <h1>Video Stream</h1>
+                <h1>Video Stream - {camera}</h1>
+                <nav>{camera_links}</nav>
                 <img id="videoStream" style="width: 640px; height: 480px;">
 
                 <script>
                     const img = document.getElementById('videoStream');
-                    const ws = new WebSocket('{}');
+                    const wsScheme = location.protocol === 'https:' ? 'wss://' : 'ws://';
+                    const ws = new WebSocket(wsScheme + location.host + '{ws_path}');
                     console.log('Connecting to WebSocket server...');
                     ws.onopen = function () {{
                         console.log('WebSocket connection established');
@@ -140,9 +607,6 @@ async fn index_handler(State(state): State<ServerState>) -> impl IntoResponse {
                     }};
                 </script>
                 </body>
-                </html>"#,
-        state.ws_path
-    );
-
-    Html(html)
+                </html>"#
+    )
 }